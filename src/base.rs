@@ -1,15 +1,26 @@
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub mod driver;
 use driver::logger::Logger;
-use driver::state::State;
-use driver::cache::Cache;
+use driver::state::{State, Snapshot};
+use driver::dump::Dump;
+use driver::cache::{Cache, CacheError, CacheKey, Storage};
 use driver::camera::Camera;
 use driver::clipboard::Clipboard;
-use driver::runtime::{Runtime, Tasks};
+use driver::runtime::{Runtime, Spawner, Task, TaskHandle, TaskMetrics, Tasks};
+use driver::channel::Mailbox;
+use driver::ipc::Ipc;
+use driver::clock::Clock;
 
 pub mod window;
+use window::{WindowId, WindowRequest};
+
+///Cache namespace of the persisted State, saved under SNAPSHOT
+const STATE: &str = "rust_on_rails::state";
+const SNAPSHOT: &str = "snapshot";
 
 pub mod renderer;
 pub use renderer::Renderer;
@@ -17,6 +28,12 @@ pub use renderer::*;
 
 pub trait BaseAppTrait<R: Renderer> {
     const LOG_LEVEL: log::Level;
+    ///Where the Cache keeps its values
+    const STORAGE: Storage = Storage::Sqlite;
+    ///Approximate size in bytes the Cache is kept under by evicting the least recently used entries
+    const CACHE_QUOTA: Option<u64> = None;
+    ///Key the Cache encrypts values with, set before the persisted State is restored or any task runs
+    fn cache_key(_cache: &Cache) -> Option<CacheKey> where Self: Sized {None}
     fn background_tasks(ctx: &mut HeadlessContext) -> impl Future<Output = Tasks> where Self: Sized;
     fn new(
         ctx: Context<R>, h_ctx: &mut HeadlessContext, width: f32, height: f32
@@ -24,23 +41,84 @@ pub trait BaseAppTrait<R: Renderer> {
     fn on_event(&mut self, event: R::Event);
     fn close(self) -> impl Future<Output = Context<R>>;
     fn ctx(&mut self) -> &mut Context<R>;
+
+    ///Called once a window asked for with Context::open_window is open, with its own renderer context
+    fn open_window(&mut self, _id: WindowId, _ctx: R::Context, _width: f32, _height: f32) -> impl Future<Output = ()> {
+        async {}
+    }
+    fn on_window_event(&mut self, _id: WindowId, _event: R::Event) {}
+    fn window_ctx(&mut self, _id: WindowId) -> Option<&mut R::Context> {None}
+    ///Gives back the renderer context of a window that is closing
+    fn close_window(&mut self, _id: WindowId) -> Option<R::Context> {None}
 }
 
 #[derive(Debug, Clone)]
 pub struct HeadlessContext {
     pub cache: Cache,
+    spawner: Spawner,
+    posted: Mailbox,
+    commands: Mailbox,
+    ipc: Option<Ipc>,
 }
 
 impl HeadlessContext {
-    async fn new(storage_path: PathBuf) -> Self {
+    fn new(cache: Cache, spawner: Spawner) -> Self {
         HeadlessContext{
-            cache: Cache::new(storage_path).await,
+            cache,
+            spawner,
+            posted: Mailbox::default(),
+            commands: Mailbox::default(),
+            ipc: None,
         }
     }
+
+    ///Queues a message for the app, picked up with Context::receive, the oldest of a type being
+    ///dropped once too many are waiting
+    pub fn post<M: Send + 'static>(&self, message: M) {self.posted.send(message)}
+
+    ///Takes the oldest command of type C sent by the app with Context::send
+    pub fn receive<C: Send + 'static>(&self) -> Option<C> {self.commands.receive()}
+
+    ///Waits for the next command of type C sent by the app
+    pub async fn next<C: Send + 'static>(&self) -> C {self.commands.next().await}
+
+    ///Context whose tasks run on the current tokio runtime and are timed by the given clock,
+    ///for testing tasks with a ManualClock
+    pub fn with_clock(storage_path: PathBuf, clock: impl Clock + 'static) -> Self {
+        Self::new(Cache::open(storage_path, Storage::Sqlite, None), Spawner::with_clock(clock))
+    }
+
+    ///Context with an in-memory Cache whose tasks run on the current tokio runtime,
+    ///for testing tasks and plugins without touching the disk
+    pub fn in_memory(clock: impl Clock + 'static) -> Self {
+        Self::new(Cache::memory(), Spawner::with_clock(clock))
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {self.spawner.clock()}
+
+    ///Run counts, durations and errors of the tasks, oldest first, including the last few that finished
+    pub fn task_metrics(&self) -> Vec<TaskMetrics> {self.spawner.metrics()}
+
+    ///Channel to the other desktop process, the UI when running in the background process and
+    ///the background process when running in the UI, None on mobile where both share one process
+    pub fn ipc(&self) -> Option<&Ipc> {self.ipc.as_ref()}
+
+    ///Starts running a task alongside the ones registered at startup
+    pub fn spawn(&self, task: impl Task + 'static) -> TaskHandle {
+        self.spawner.spawn(self.clone(), Box::new(task))
+    }
 }
 
+///Id of the next window opened with Context::open_window
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(1);
+
 pub struct Context<R: Renderer> {
     state: State,
+    ///Windows to open or close once the current event is handled
+    windows: Vec<WindowRequest>,
+    ///Revision of the State when it was last saved or restored
+    saved: u64,
+    h_ctx: HeadlessContext,
     r_ctx: R::Context
 }
 
@@ -49,25 +127,97 @@ impl<R: Renderer> AsMut<R::Context> for Context<R> {
 }
 
 impl<R: Renderer> Context<R> {
-    fn new(r_ctx: R::Context) -> Self {
-        Context{state: State::default(), r_ctx}
+    fn new(r_ctx: R::Context, h_ctx: HeadlessContext) -> Self {
+        Context{state: State::default(), windows: Vec::new(), saved: 0, h_ctx, r_ctx}
     }
 
     pub fn state(&mut self) -> &mut State {&mut self.state}
 
+    ///Contents of the State and Cache as a human readable document, for debugging
+    pub fn dump(&self) -> Result<Dump, CacheError> {
+        Ok(Dump{state: self.state.dump(), cache: self.cache().dump()?})
+    }
+
+    ///Replaces the contents of the State and Cache with those of a dump
+    pub fn load(&mut self, dump: Dump) -> Result<(), CacheError> {
+        self.cache().load(dump.cache)?;
+        self.state.load(dump.state);
+        Ok(())
+    }
+
+    ///Saves the persisted fields of the State to the Cache, unless it is unchanged since the last save
+    async fn save_state(&mut self) {
+        if self.state.revision() == self.saved {return;}
+        match self.cache().namespace(STATE).set(SNAPSHOT, &self.state.snapshot()).await {
+            Ok(()) => self.saved = self.state.revision(),
+            Err(error) => log::warn!("Could not save state: {}", error)
+        }
+    }
+
+    ///Restores the fields persisted by the last run
+    async fn restore_state(&mut self) {
+        match self.cache().namespace(STATE).get::<Snapshot>(SNAPSHOT).await {
+            Ok(snapshot) => {
+                self.state.restore(snapshot.unwrap_or_default());
+                self.saved = self.state.revision();
+            },
+            Err(error) => log::warn!("Could not restore state: {}", error)
+        }
+    }
+
+    ///Asks for another window, opened once the current event is handled, size being in logical pixels
+    ///or the platform default if None
+    pub fn open_window(&mut self, title: &str, size: Option<(u32, u32)>) -> WindowId {
+        let id = WindowId(NEXT_WINDOW.fetch_add(1, Ordering::Relaxed));
+        self.windows.push(WindowRequest::Open{id, title: title.to_string(), size});
+        id
+    }
+
+    ///Closes a window opened with open_window, the main window can't be closed
+    pub fn close_window(&mut self, id: WindowId) {
+        if id != WindowId::MAIN {self.windows.push(WindowRequest::Close(id));}
+    }
+
+    pub fn spawn(&mut self, task: impl Task + 'static) -> TaskHandle {self.h_ctx.spawn(task)}
+
+    pub fn clock(&self) -> Arc<dyn Clock> {self.h_ctx.clock()}
+
+    pub fn cache(&self) -> &Cache {&self.h_ctx.cache}
+
+    pub fn ipc(&self) -> Option<&Ipc> {self.h_ctx.ipc()}
+
+    pub fn task_metrics(&self) -> Vec<TaskMetrics> {self.h_ctx.task_metrics()}
+
+    ///Sends a command to the Tasks, picked up with HeadlessContext::receive
+    pub fn send<C: Send + 'static>(&mut self, command: C) {self.h_ctx.commands.send(command)}
+
+    ///Takes the oldest message of type M posted by a Task
+    pub fn receive<M: Send + 'static>(&mut self) -> Option<M> {self.h_ctx.posted.receive()}
+
     pub fn open_camera() -> Camera { Camera::new() }
     pub fn get_clipboard(&mut self) -> String { Clipboard::get() }
     pub fn set_clipboard(&mut self, t: String) { Clipboard::set(t) }
 }
 
+///Opens the Cache of the app with its key set, before anything reads from it
+fn open_cache<R: Renderer, A: BaseAppTrait<R>>(storage_path: &Path) -> Cache {
+    let cache = Cache::open(storage_path.to_path_buf(), A::STORAGE, A::CACHE_QUOTA);
+    cache.set_key(A::cache_key(&cache));
+    cache
+}
+
 pub struct BackgroundApp;
 impl BackgroundApp {
     pub fn new_start<R: Renderer, A: BaseAppTrait<R>>(storage_path: PathBuf) {
         #[cfg(not(target_arch="wasm32"))]
-        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().enable_io().build().unwrap();
         #[cfg(not(target_arch="wasm32"))]
         runtime.block_on(async {
-            let mut ctx = HeadlessContext::new(storage_path).await;
+            let mut ctx = HeadlessContext::new(open_cache::<R, A>(&storage_path), Spawner::current());
+            //No app runs in this process to take what tasks post, such as TaskFailed
+            ctx.posted = Mailbox::discarding();
+            #[cfg(unix)]
+            {ctx.ipc = Some(Ipc::serve(&storage_path, ctx.spawner.handle()));}
             let tasks = A::background_tasks(&mut ctx).await;
             Runtime::new_background(ctx, tasks).await;
         });
@@ -90,31 +240,56 @@ impl<R: Renderer, A: BaseAppTrait<R>> RenderAppTrait<R> for BaseApp<R, A> {
         storage_path: PathBuf, ctx: R::Context, width: f32, height: f32
     ) -> Self {
         Logger::start(A::LOG_LEVEL);        
-        let mut headless_ctx = HeadlessContext::new(storage_path.clone()).await;
-        let ctx = Context::new(ctx);
+        let runtime = Runtime::new();
+        let mut headless_ctx = HeadlessContext::new(open_cache::<R, A>(&storage_path), runtime.spawner());
+        #[cfg(all(unix, not(any(target_os = "ios", target_os = "android"))))]
+        {headless_ctx.ipc = Some(Ipc::connect(&storage_path, headless_ctx.spawner.handle()));}
+        let mut ctx = Context::new(ctx, headless_ctx.clone());
+        ctx.restore_state().await;
         let background_tasks = if cfg!(any(target_os = "ios", target_os = "android")) {
             A::background_tasks(&mut headless_ctx).await
         } else {vec![]};
         let (app, tasks) = A::new(ctx, &mut headless_ctx, width, height).await;
-        let runtime = Runtime::new(headless_ctx, background_tasks, tasks).await;
+        runtime.start(&headless_ctx, background_tasks, tasks);
         BaseApp{
             _p: std::marker::PhantomData::<R>,
             runtime, app
         }
     }
     async fn on_event(&mut self, event: R::Event) {
-        if event.is_paused() {self.runtime.pause();}
+        let paused = event.is_paused();
+        if paused {self.runtime.pause();}
         if event.is_resumed() {self.runtime.resume();}
         self.app.on_event(event);
+        if paused {self.app.ctx().save_state().await;}
     }
 
     async fn close(mut self) -> R::Context {
-        let ctx = self.app.close().await;
-        self.runtime.close();
+        let mut ctx = self.app.close().await;
+        ctx.save_state().await;
+        self.runtime.close().await;
         ctx.r_ctx
     }
 
     fn ctx(&mut self) -> &mut R::Context {&mut self.app.ctx().r_ctx}
+
+    async fn open_window(&mut self, id: WindowId, ctx: R::Context, width: f32, height: f32) {
+        self.app.open_window(id, ctx, width, height).await
+    }
+
+    async fn on_window_event(&mut self, id: WindowId, event: R::Event) {
+        if id == WindowId::MAIN {return self.on_event(event).await;}
+        self.app.on_window_event(id, event);
+    }
+
+    fn window_ctx(&mut self, id: WindowId) -> Option<&mut R::Context> {
+        if id == WindowId::MAIN {return Some(self.ctx());}
+        self.app.window_ctx(id)
+    }
+
+    async fn close_window(&mut self, id: WindowId) -> Option<R::Context> {self.app.close_window(id)}
+
+    fn window_requests(&mut self) -> Vec<WindowRequest> {std::mem::take(&mut self.app.ctx().windows)}
 }
 
 #[macro_export]
@@ -157,3 +332,67 @@ macro_rules! create_base_entry_points {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Headless;
+
+    struct Lifeless;
+
+    impl HasLifeEvents for Lifeless {
+        fn is_resumed(&self) -> bool {false}
+        fn is_paused(&self) -> bool {false}
+    }
+
+    impl Renderer for Headless {
+        type Context = ();
+        type Event = Lifeless;
+
+        async fn new<W: window::WindowHandle + 'static>(_: W, _: u32, _: u32, _: f64) -> (Self, (), (f32, f32)) {
+            unimplemented!()
+        }
+        async fn on_event<W: window::WindowHandle, A: RenderAppTrait<Self>>(
+            &mut self, _: &mut A, _: WindowId, _: window::WindowEvent<W>
+        ) {}
+        async fn close(self, _: ()) {}
+    }
+
+    struct Encrypted(Context<Headless>);
+
+    impl BaseAppTrait<Headless> for Encrypted {
+        const LOG_LEVEL: log::Level = log::Level::Error;
+        fn cache_key(_cache: &Cache) -> Option<CacheKey> {Some(CacheKey::new([7; 32]))}
+        async fn background_tasks(_ctx: &mut HeadlessContext) -> Tasks {vec![]}
+        async fn new(ctx: Context<Headless>, _: &mut HeadlessContext, _: f32, _: f32) -> (Self, Tasks) {(Encrypted(ctx), vec![])}
+        fn on_event(&mut self, _: Lifeless) {}
+        async fn close(self) -> Context<Headless> {self.0}
+        fn ctx(&mut self) -> &mut Context<Headless> {&mut self.0}
+    }
+
+    fn context(storage_path: &Path) -> Context<Headless> {
+        Context::new((), HeadlessContext::new(open_cache::<Headless, Encrypted>(storage_path), Spawner::current()))
+    }
+
+    #[tokio::test]
+    async fn restores_encrypted_state() {
+        let dir = std::env::temp_dir().join(format!("rust_on_rails_encrypted_state_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut ctx = context(&dir);
+        ctx.state().set(&7u64);
+        ctx.state().persist::<u64>();
+        ctx.save_state().await;
+        drop(ctx);
+
+        let cache = Cache::open(dir.clone(), Storage::Sqlite, None);
+        assert!(matches!(cache.namespace(STATE).get::<Snapshot>(SNAPSHOT).await, Err(CacheError::Encrypted{..})));
+        drop(cache);
+
+        let mut ctx = context(&dir);
+        ctx.restore_state().await;
+        assert_eq!(ctx.state().get::<u64>(), 7);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
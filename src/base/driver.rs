@@ -1,8 +1,13 @@
 pub mod runtime;
+pub mod channel;
+pub mod ipc;
+pub mod clock;
 pub mod logger;
 pub mod camera;
 pub mod state;
+pub mod schema;
 pub mod cache;
+pub mod dump;
 pub mod cloud;
 pub mod share;
 pub mod haptics;
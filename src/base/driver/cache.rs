@@ -1,51 +1,706 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::path::PathBuf;
 use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-#[cfg(target_os = "android")]
-use winit_crate::platform::android::activity::AndroidApp;
+use tokio::sync::broadcast;
 
+use super::state::Field;
+use super::schema::Schema;
+use super::dump::{CacheDump, DumpedEntry};
+use super::runtime::LAST_RUNS;
+use crate::base::STATE;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod sqlite;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sqlite::SqliteBackend;
+#[cfg(not(target_arch = "wasm32"))]
+mod file;
 #[cfg(not(target_arch = "wasm32"))]
-use tokio::sync::Mutex;
+pub use file::FileBackend;
+mod memory;
+pub use memory::MemoryBackend;
+mod transaction;
+pub use transaction::{Transaction, Op};
+mod encryption;
+pub use encryption::CacheKey;
+use encryption::{Keys, KEYS};
 
-use super::state::Field;
+///Namespace of the values stored with Cache::set, keyed by Field::ident
+const DEFAULT: &str = "";
+
+///Framework namespaces never expired nor evicted, as they are read once at startup and rarely
+///written after, which would make them the first entries evicted
+const PINNED: [&str; 3] = [KEYS, STATE, LAST_RUNS];
+
+///Expired entries are purged and the quota enforced after this many writes, and when the cache opens
+const PURGE_EVERY: u32 = 256;
+
+///Changes buffered for each watcher before the oldest are dropped
+const CHANGES: usize = 1024;
+
+///Reads only record the access time of an entry when it is older than this, sparing a write per read
+const TOUCH_AFTER: u64 = 60;
+
+///Watches look for changes made by other processes at most this often
+const POLL_EVERY: Duration = Duration::from_millis(250);
+
+///Seconds since the unix epoch, the unit of Entry::expires and Entry::accessed
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+///Expiry of an entry written now to live for ttl, rounded up to the next whole second so it is
+///never expired before ttl has passed, while a zero ttl expires at once
+fn expires_in(ttl: Duration) -> u64 {
+    if ttl.is_zero() {return now();}
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() + ttl;
+    expires.as_secs() + u64::from(expires.subsec_nanos() > 0)
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(std::io::Error),
+    #[cfg(not(target_arch = "wasm32"))]
+    Sqlite(rusqlite::Error),
+    ///A stored value could not be read back as the requested type
+    Decode{namespace: String, key: String, error: String},
+    ///A stored value is encrypted with none of the keys given to the Cache
+    Encrypted{namespace: String, key: String},
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Io(error) => write!(f, "Cache storage error: {}", error),
+            #[cfg(not(target_arch = "wasm32"))]
+            CacheError::Sqlite(error) => write!(f, "Cache database error: {}", error),
+            CacheError::Decode{namespace, key, error} => write!(f, "Could not decode {}/{}: {}", namespace, key, error),
+            CacheError::Encrypted{namespace, key} => write!(f, "{}/{} is encrypted with an unknown key", namespace, key),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::Io(error) => Some(error),
+            #[cfg(not(target_arch = "wasm32"))]
+            CacheError::Sqlite(error) => Some(error),
+            CacheError::Decode{..} | CacheError::Encrypted{..} => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CacheError {
+    fn from(error: std::io::Error) -> Self {CacheError::Io(error)}
+}
 
 #[cfg(not(target_arch = "wasm32"))]
+impl From<rusqlite::Error> for CacheError {
+    fn from(error: rusqlite::Error) -> Self {CacheError::Sqlite(error)}
+}
+
+///Where the Cache keeps its values, chosen by the app at startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    ///cache.db in the storage path
+    #[default]
+    Sqlite,
+    ///A JSON file per namespace in the cache directory of the storage path
+    File,
+    ///Nothing is written to disk, the only storage available on wasm
+    Memory,
+}
+
+///Encoded Field value with the times used for expiry and eviction, in seconds since the unix epoch,
+///and the Schema version it was encoded with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub value: Vec<u8>,
+    pub expires: Option<u64>,
+    pub accessed: u64,
+    pub version: u32,
+}
+
+impl Entry {
+    fn new<F: Field + 'static>(item: &F, expires: Option<u64>) -> Self {
+        Entry{value: Schema::encode(item), expires, accessed: now(), version: Schema::version::<F>()}
+    }
+
+    fn expired(&self, now: u64) -> bool {self.expires.is_some_and(|expires| expires <= now)}
+}
+
+///Approximate stored size and times of an entry, listed when purging
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub namespace: String,
+    pub key: String,
+    pub size: u64,
+    pub expires: Option<u64>,
+    pub accessed: u64,
+}
+
+///Storage of encoded Field values by namespace and key behind a Cache
+pub trait Backend: Debug + Send {
+    fn get(&mut self, namespace: &str, key: &str) -> Result<Option<Entry>, CacheError>;
+    fn set(&mut self, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError>;
+    ///Records that the entry was read at the given time
+    fn touch(&mut self, namespace: &str, key: &str, accessed: u64) -> Result<(), CacheError>;
+    ///Removes the value under key, returning whether there was one
+    fn remove(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError>;
+    ///Keys in the namespace in ascending order
+    fn keys(&mut self, namespace: &str) -> Result<Vec<String>, CacheError>;
+    ///Every entry in the namespace with its key, in key order
+    fn entries(&mut self, namespace: &str) -> Result<Vec<(String, Entry)>, CacheError>;
+    fn clear(&mut self, namespace: &str) -> Result<(), CacheError>;
+    ///Removes the entries of the namespace that expired at or before now, returning their keys
+    fn expire(&mut self, namespace: &str, now: u64) -> Result<Vec<String>, CacheError>;
+    ///Every entry in every namespace, without its value
+    fn usage(&mut self) -> Result<Vec<Usage>, CacheError>;
+
+    fn contains(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        Ok(self.get(namespace, key)?.is_some())
+    }
+
+    ///Keys set or removed by other processes since the last call, for storage shared between them
+    fn external(&mut self) -> Result<Vec<(String, String)>, CacheError> {Ok(Vec::new())}
+
+    ///Applies the operations in order, all of them or none
+    ///
+    ///The default applies them one at a time, which is only atomic for backends that can't fail.
+    fn apply(&mut self, ops: Vec<Op>) -> Result<(), CacheError> {
+        for op in ops {
+            match op {
+                Op::Set{namespace, key, entry} => self.set(&namespace, &key, entry)?,
+                Op::Remove{namespace, key} => {self.remove(&namespace, &key)?;}
+            }
+        }
+        Ok(())
+    }
+}
+
+///A value in the Cache was set or removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub namespace: String,
+    pub key: String,
+    ///Encoded new value, None once removed or expired
+    pub value: Option<Vec<u8>>,
+}
+
+impl Change {
+    pub fn decode<F: Field + 'static>(&self) -> Result<Changed<F>, CacheError> {
+        let value = self.value.as_ref().map(|bytes| Schema::decode(Schema::version::<F>(), bytes).map(|(item, _)| item))
+            .transpose().map_err(|error| CacheError::Decode{namespace: self.namespace.clone(), key: self.key.clone(), error})?;
+        Ok(Changed{namespace: self.namespace.clone(), key: self.key.clone(), value})
+    }
+}
+
+///A watched value of type F was set or removed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changed<F> {
+    pub namespace: String,
+    pub key: String,
+    pub value: Option<F>,
+}
+
+///Changes to one key, or every key, of a namespace, decoded as F
+#[derive(Debug)]
+pub struct Watch<F> {
+    cache: Cache,
+    changes: broadcast::Receiver<Change>,
+    namespace: String,
+    key: Option<String>,
+    _p: PhantomData<fn() -> F>,
+}
+
+impl<F: Field + 'static> Watch<F> {
+    fn matches(&self, change: &Change) -> Option<Changed<F>> {
+        if change.namespace != self.namespace || self.key.as_ref().is_some_and(|key| *key != change.key) {return None;}
+        change.decode().inspect_err(|error| log::warn!("Ignored cache change: {}", error)).ok()
+    }
+
+    ///Waits for the next change, including those made by another process sharing the storage
+    pub async fn next(&mut self) -> Changed<F> {
+        loop {
+            #[cfg(not(target_arch = "wasm32"))]
+            let change = tokio::select! {
+                change = self.changes.recv() => change,
+                _ = tokio::time::sleep(POLL_EVERY) => {self.cache.poll(); continue;}
+            };
+            #[cfg(target_arch = "wasm32")]
+            let change = self.changes.recv().await;
+            match change {
+                Ok(change) => if let Some(changed) = self.matches(&change) {return changed;},
+                Err(broadcast::error::RecvError::Lagged(missed)) => log::warn!("Cache watch missed {} changes", missed),
+                Err(broadcast::error::RecvError::Closed) => std::future::pending().await
+            }
+        }
+    }
+
+    ///Takes the oldest change that has not been seen yet
+    pub fn try_next(&mut self) -> Option<Changed<F>> {
+        self.cache.poll();
+        loop {
+            match self.changes.try_recv() {
+                Ok(change) => if let Some(changed) = self.matches(&change) {return Some(changed);},
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => log::warn!("Cache watch missed {} changes", missed),
+                Err(_) => return None
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    backend: Box<dyn Backend>,
+    quota: Option<u64>,
+    writes: u32,
+    changes: broadcast::Sender<Change>,
+    keys: Keys,
+    ///When changes made by other processes were last looked for
+    polled: Option<Instant>,
+}
+
+impl Inner {
+    fn changed(&self, namespace: &str, key: &str, value: Option<Vec<u8>>) {
+        let _ = self.changes.send(Change{namespace: namespace.to_string(), key: key.to_string(), value});
+    }
+
+    ///Decrypts a stored entry, returning whether it is stored as the current key would store it
+    fn open(&self, namespace: &str, key: &str, mut entry: Entry) -> Result<(Entry, bool), CacheError> {
+        let (value, current) = self.keys.open(namespace, key, entry.value).ok_or_else(|| CacheError::Encrypted{
+            namespace: namespace.to_string(), key: key.to_string()
+        })?;
+        entry.value = value;
+        Ok((entry, current))
+    }
+
+    fn seal(&self, namespace: &str, key: &str, mut entry: Entry) -> Entry {
+        entry.value = self.keys.seal(namespace, key, entry.value);
+        entry
+    }
+
+    fn get(&mut self, namespace: &str, key: &str) -> Result<Option<Entry>, CacheError> {
+        let Some(entry) = self.backend.get(namespace, key)? else {return Ok(None);};
+        Ok(Some(self.open(namespace, key, entry)?.0))
+    }
+
+    fn set(&mut self, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError> {
+        let entry = self.seal(namespace, key, entry);
+        self.backend.set(namespace, key, entry)
+    }
+
+    fn entries(&mut self, namespace: &str) -> Result<Vec<(String, Entry)>, CacheError> {
+        self.backend.entries(namespace)?.into_iter()
+            .map(|(key, entry)| Ok((key.clone(), self.open(namespace, &key, entry)?.0))).collect()
+    }
+
+    fn seal_op(&self, op: Op) -> Op {
+        match op {
+            Op::Set{namespace, key, entry} => Op::Set{entry: self.seal(&namespace, &key, entry), namespace, key},
+            op => op
+        }
+    }
+
+    ///Applies the operations atomically, then notifies watchers of each
+    fn apply(&mut self, ops: Vec<Op>) -> Result<(), CacheError> {
+        let sealed = ops.iter().cloned().map(|op| self.seal_op(op)).collect();
+        self.store(sealed, ops)
+    }
+
+    ///Applies the operations as they will be stored atomically, then notifies watchers of each
+    ///of the operations they were sealed from
+    fn store(&mut self, sealed: Vec<Op>, ops: Vec<Op>) -> Result<(), CacheError> {
+        self.backend.apply(sealed)?;
+        for op in ops {
+            match op {
+                Op::Set{namespace, key, entry} => self.changed(&namespace, &key, Some(entry.value)),
+                Op::Remove{namespace, key} => self.changed(&namespace, &key, None)
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Cache(
-    Arc<Mutex<rusqlite::Connection>>
+    Arc<Mutex<Inner>>
 );
 
-#[cfg(not(target_arch = "wasm32"))]
 impl Cache {
-    pub(crate) async fn new(storage_path: PathBuf) -> Self {
-        std::fs::create_dir_all(&storage_path).unwrap();
-        let path = storage_path.join("cache.db");
-        let db = rusqlite::Connection::open(path).unwrap();
-        db.execute(
-            "CREATE TABLE if not exists kvs(key TEXT NOT NULL UNIQUE, value TEXT);", []
-        ).unwrap();
-        Cache(Arc::new(Mutex::new(db)))
-    }
-
-    pub async fn set<F: Field + 'static>(&self, item: &F) {
-        self.0.lock().await.execute(
-            "INSERT INTO kvs(key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value;",
-            [F::ident(), hex::encode(item.to_bytes())]
-        ).unwrap();
-    }
-    pub async fn get<F: Field + 'static>(&self) -> F {
-        let db = self.0.lock().await;
-        let mut stmt = db.prepare(&format!(
-            "SELECT value FROM kvs where key = \'{}\'",
-            F::ident()
-        )).unwrap();
-        let result = stmt.query_and_then([], |row| {
-            let item: String = row.get(0).unwrap();
-            Ok(hex::decode(item).unwrap())
-        }).unwrap().collect::<Result<Vec<Vec<u8>>, rusqlite::Error>>().unwrap();
-        result.first().map(|b| F::from_bytes(b)).unwrap_or_default()
-    }
-}
-
-//TODO: WASM Cache
+    pub fn new(backend: impl Backend + 'static) -> Self {
+        Cache(Arc::new(Mutex::new(Inner{
+            backend: Box::new(backend), quota: None, writes: 0, changes: broadcast::channel(CHANGES).0, keys: Keys::default(), polled: None
+        })))
+    }
+
+    ///Cache that keeps nothing on disk
+    pub fn memory() -> Self {Self::new(MemoryBackend::default())}
+
+    ///Opens the chosen storage in the storage path, keeping values in memory if it can't be opened
+    pub(crate) fn open(storage_path: PathBuf, storage: Storage, quota: Option<u64>) -> Self {
+        let backend: Result<Self, CacheError> = match storage {
+            #[cfg(not(target_arch = "wasm32"))]
+            Storage::Sqlite => SqliteBackend::open_or_recover(&storage_path.join("cache.db")).map(Self::new),
+            #[cfg(not(target_arch = "wasm32"))]
+            Storage::File => FileBackend::open(storage_path.join("cache")).map(Self::new),
+            _ => Ok(Self::memory())
+        };
+        let cache = backend.unwrap_or_else(|error| {
+            log::error!("Could not open {:?} cache in {}, keeping it in memory: {}", storage, storage_path.display(), error);
+            Self::memory()
+        });
+        if let Err(error) = cache.set_quota(quota) {log::warn!("Could not purge cache: {}", error);}
+        cache
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {self.0.lock().unwrap()}
+
+    ///Purges after a write that is already stored, logging a failure rather than failing the write
+    fn purge_after_write(&self) {
+        if let Err(error) = self.purge() {log::warn!("Could not purge cache: {}", error);}
+    }
+
+    ///Limits the approximate total size of the stored entries in bytes, evicting the least recently
+    ///used entries once it is exceeded
+    pub fn set_quota(&self, quota: Option<u64>) -> Result<(), CacheError> {
+        self.inner().quota = quota;
+        self.purge().map(|_| ())
+    }
+
+    ///Removes expired entries, then the least recently used ones until the quota is met,
+    ///returning how many were removed
+    ///
+    ///This also runs automatically when the cache opens and every few hundred writes. The saved State,
+    ///the last run times of tasks and the encryption salt are never removed.
+    pub fn purge(&self) -> Result<usize, CacheError> {
+        let mut inner = self.inner();
+        inner.writes = 0;
+        let now = now();
+        let (expired, mut usage): (Vec<_>, Vec<_>) = inner.backend.usage()?.into_iter()
+            .filter(|usage| !PINNED.contains(&usage.namespace.as_str()))
+            .partition(|usage| usage.expires.is_some_and(|expires| expires <= now));
+        let mut removed = expired.into_iter().map(|usage| (usage.namespace, usage.key)).collect::<Vec<_>>();
+        if let Some(quota) = inner.quota {
+            let mut total = usage.iter().map(|usage| usage.size).sum::<u64>();
+            usage.sort_by_key(|usage| usage.accessed);
+            for usage in usage {
+                if total <= quota {break;}
+                total -= usage.size;
+                removed.push((usage.namespace, usage.key));
+            }
+        }
+        for (namespace, key) in &removed {
+            inner.backend.remove(namespace, key)?;
+            inner.changed(namespace, key, None);
+        }
+        Ok(removed.len())
+    }
+
+    ///Encrypts every value written from now on with the key, or stops encrypting them with None
+    ///
+    ///The key it replaces is kept to read the values it encrypted, so rotating keys means setting
+    ///the old key, then the new one, and calling rekey. Values stored before any key was set
+    ///are read as they are.
+    pub fn set_key(&self, key: Option<CacheKey>) {self.inner().keys.set(key);}
+
+    ///Rewrites every value not yet stored as the current key would store it, encrypting plaintext
+    ///values and those encrypted with an older key, returning how many were rewritten
+    pub fn rekey(&self) -> Result<usize, CacheError> {
+        let mut inner = self.inner();
+        let mut rewritten = 0;
+        for usage in inner.backend.usage()? {
+            let Some(entry) = inner.backend.get(&usage.namespace, &usage.key)? else {continue;};
+            let (entry, current) = inner.open(&usage.namespace, &usage.key, entry)?;
+            if !current {
+                inner.set(&usage.namespace, &usage.key, entry)?;
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+
+    ///Random salt for CacheKey::from_passphrase, created on first use and stored unencrypted
+    pub fn salt(&self) -> Result<Vec<u8>, CacheError> {
+        let mut inner = self.inner();
+        if let Some(entry) = inner.backend.get(KEYS, "salt")? {return Ok(entry.value);}
+        let salt = encryption::salt();
+        inner.backend.set(KEYS, "salt", Entry{value: salt.clone(), expires: None, accessed: now(), version: 0})?;
+        Ok(salt)
+    }
+
+    ///Every unexpired entry of every namespace decoded to JSON, decrypted if a key opens it
+    pub fn dump(&self) -> Result<CacheDump, CacheError> {
+        let mut inner = self.inner();
+        let now = now();
+        let mut dump = CacheDump::new();
+        for usage in inner.backend.usage()? {
+            if usage.namespace == KEYS || usage.expires.is_some_and(|expires| expires <= now) {continue;}
+            let Some(entry) = inner.backend.get(&usage.namespace, &usage.key)? else {continue;};
+            let entry = inner.open(&usage.namespace, &usage.key, entry.clone()).map(|(entry, _)| entry).unwrap_or(entry);
+            let value = Schema::inspect(&entry.value);
+            let dumped = DumpedEntry{
+                raw: value.is_none().then(|| hex::encode(&entry.value)),
+                value: value.unwrap_or_default(),
+                version: entry.version,
+                expires: entry.expires,
+            };
+            dump.entry(usage.namespace).or_default().insert(usage.key, dumped);
+        }
+        Ok(dump)
+    }
+
+    ///Replaces every entry with those of a dump, stored as JSON and encrypted if a key is set
+    ///
+    ///Raw entries are stored exactly as they were dumped, as they are either not JSON or still
+    ///encrypted with a key that could not open them.
+    pub fn load(&self, dump: CacheDump) -> Result<(), CacheError> {
+        let mut inner = self.inner();
+        let mut ops = inner.backend.usage()?.into_iter().filter(|usage| usage.namespace != KEYS)
+            .map(|usage| Op::Remove{namespace: usage.namespace, key: usage.key}).collect::<Vec<_>>();
+        let mut sealed = ops.clone();
+        for (namespace, entries) in dump {
+            for (key, dumped) in entries {
+                let value = match &dumped.raw {
+                    Some(raw) => hex::decode(raw).map_err(|error| CacheError::Decode{
+                        namespace: namespace.clone(), key: key.clone(), error: error.to_string()
+                    })?,
+                    None => serde_json::to_vec(&dumped.value).unwrap()
+                };
+                let entry = Entry{value, expires: dumped.expires, accessed: now(), version: dumped.version};
+                let op = Op::Set{namespace: namespace.clone(), key, entry};
+                sealed.push(if dumped.raw.is_some() {op.clone()} else {inner.seal_op(op.clone())});
+                ops.push(op);
+            }
+        }
+        inner.store(sealed, ops)
+    }
+
+    ///Groups writes so they are stored together on commit
+    pub fn transaction(&self) -> Transaction {Transaction::new(self.clone())}
+
+    ///Every change to every namespace, as it happens, those of other processes once poll picks them up
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {self.inner().changes.subscribe()}
+
+    ///Notifies watchers of the changes another process made to the storage they share, such as the
+    ///background process writing to cache.db, looking at most every POLL_EVERY
+    ///
+    ///Watches call this while they wait, so it is only needed with subscribe.
+    pub fn poll(&self) {
+        let mut inner = self.inner();
+        if inner.polled.is_some_and(|polled| polled.elapsed() < POLL_EVERY) {return;}
+        inner.polled = Some(Instant::now());
+        let keys = match inner.backend.external() {
+            Ok(keys) => keys,
+            Err(error) => return log::warn!("Could not read cache changes of other processes: {}", error)
+        };
+        for (namespace, key) in keys {
+            match inner.get(&namespace, &key) {
+                Ok(entry) => inner.changed(&namespace, &key, entry.map(|entry| entry.value)),
+                Err(error) => log::warn!("Ignored cache change of another process: {}", error)
+            }
+        }
+    }
+
+    ///Changes to the value of type F stored with set
+    pub fn watch<F: Field + 'static>(&self) -> Watch<F> {self.namespace(DEFAULT).watch(&F::ident())}
+
+    pub async fn set<F: Field + 'static>(&self, item: &F) -> Result<(), CacheError> {
+        self.namespace(DEFAULT).set(&F::ident(), item).await
+    }
+
+    ///Stores the value of type F until the time to live has passed
+    pub async fn set_expiring<F: Field + 'static>(&self, item: &F, ttl: Duration) -> Result<(), CacheError> {
+        self.namespace(DEFAULT).set_expiring(&F::ident(), item, ttl).await
+    }
+
+    ///The stored value of type F, or its default if none was stored
+    pub async fn get<F: Field + 'static>(&self) -> Result<F, CacheError> {
+        Ok(self.namespace(DEFAULT).get(&F::ident()).await?.unwrap_or_default())
+    }
+
+    ///Values stored under their own keys, separate from every other namespace
+    pub fn namespace(&self, name: &str) -> Namespace {
+        Namespace{cache: self.clone(), name: name.to_string()}
+    }
+
+    ///Namespace holding many values of type F, named after F::ident
+    pub fn table<F: Field + 'static>(&self) -> Table<F> {
+        Table(self.namespace(&F::ident()), PhantomData)
+    }
+
+    ///Moves what was stored under an old Field ident to a new one, both the value set with Cache::set
+    ///and the table of that name, replacing anything already under the new ident
+    ///
+    ///Returns how many values were moved. Run it at startup after pinning an ident or renaming a type.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<usize, CacheError> {
+        if from == to || from == DEFAULT || to == DEFAULT {return Ok(0);}
+        let mut inner = self.inner();
+        let mut ops = Vec::new();
+        if let Some(entry) = inner.get(DEFAULT, from)? {
+            ops.push(Op::Remove{namespace: DEFAULT.to_string(), key: from.to_string()});
+            ops.push(Op::Set{namespace: DEFAULT.to_string(), key: to.to_string(), entry});
+        }
+        for (key, entry) in inner.entries(from)? {
+            ops.push(Op::Remove{namespace: from.to_string(), key: key.clone()});
+            ops.push(Op::Set{namespace: to.to_string(), key, entry});
+        }
+        let moved = ops.len() / 2;
+        inner.apply(ops)?;
+        Ok(moved)
+    }
+}
+
+///Named group of keyed values in the Cache
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    cache: Cache,
+    name: String,
+}
+
+impl Namespace {
+    pub fn name(&self) -> &str {&self.name}
+
+    ///Decodes the entry as F, migrating it first if an older Schema of F stored it,
+    ///and returns the upgraded entry to store back in that case
+    fn decode<F: Field + 'static>(&self, key: &str, entry: &Entry) -> Result<(F, Option<Entry>), CacheError> {
+        let (item, upgraded) = Schema::decode::<F>(entry.version, &entry.value).map_err(|error| CacheError::Decode{
+            namespace: self.name.clone(), key: key.to_string(), error
+        })?;
+        Ok((item, upgraded.map(|value| Entry{value, version: Schema::version::<F>(), ..entry.clone()})))
+    }
+
+    ///Decodes the entry as F, storing it back once migrated so it is only upgraded once
+    fn read<F: Field + 'static>(&self, inner: &mut Inner, key: &str, entry: &Entry) -> Result<F, CacheError> {
+        let (item, upgraded) = self.decode(key, entry)?;
+        if let Some(upgraded) = upgraded {
+            log::info!("Migrated {}/{} from version {} to {}", self.name, key, entry.version, upgraded.version);
+            inner.set(&self.name, key, upgraded)?;
+        }
+        Ok(item)
+    }
+
+    fn write(&self, key: &str, entry: Entry) -> Result<(), CacheError> {
+        let mut inner = self.cache.inner();
+        let value = entry.value.clone();
+        inner.set(&self.name, key, entry)?;
+        inner.changed(&self.name, key, Some(value));
+        inner.writes += 1;
+        let purge = inner.writes >= PURGE_EVERY;
+        drop(inner);
+        if purge {self.cache.purge_after_write();}
+        Ok(())
+    }
+
+    pub async fn set<F: Field + 'static>(&self, key: &str, item: &F) -> Result<(), CacheError> {
+        self.write(key, Entry::new(item, None))
+    }
+
+    ///Stores the value under key until the time to live has passed, after which it reads as missing
+    pub async fn set_expiring<F: Field + 'static>(&self, key: &str, item: &F, ttl: Duration) -> Result<(), CacheError> {
+        self.write(key, Entry::new(item, Some(expires_in(ttl))))
+    }
+
+    ///The value under key, migrated to the current Schema of F if an older one stored it
+    pub async fn get<F: Field + 'static>(&self, key: &str) -> Result<Option<F>, CacheError> {
+        let now = now();
+        let mut inner = self.cache.inner();
+        match inner.get(&self.name, key)? {
+            Some(entry) if entry.expired(now) => {
+                inner.backend.remove(&self.name, key)?;
+                inner.changed(&self.name, key, None);
+                Ok(None)
+            },
+            Some(entry) => {
+                if now.saturating_sub(entry.accessed) >= TOUCH_AFTER {inner.backend.touch(&self.name, key, now)?;}
+                self.read(&mut inner, key, &entry).map(Some)
+            },
+            None => Ok(None)
+        }
+    }
+
+    ///Removes the expired entries of the namespace before they are listed
+    fn expire(&self, inner: &mut Inner) -> Result<(), CacheError> {
+        for key in inner.backend.expire(&self.name, now())? {inner.changed(&self.name, &key, None);}
+        Ok(())
+    }
+
+    pub async fn contains(&self, key: &str) -> Result<bool, CacheError> {
+        let mut inner = self.cache.inner();
+        self.expire(&mut inner)?;
+        inner.backend.contains(&self.name, key)
+    }
+
+    ///Removes the value under key, returning whether there was one
+    pub async fn remove(&self, key: &str) -> Result<bool, CacheError> {
+        let mut inner = self.cache.inner();
+        let removed = inner.backend.remove(&self.name, key)?;
+        if removed {inner.changed(&self.name, key, None);}
+        Ok(removed)
+    }
+
+    ///Keys in the namespace in ascending order
+    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {
+        let mut inner = self.cache.inner();
+        self.expire(&mut inner)?;
+        inner.backend.keys(&self.name)
+    }
+
+    ///Removes every value in the namespace
+    pub async fn clear(&self) -> Result<(), CacheError> {
+        let mut inner = self.cache.inner();
+        let keys = inner.backend.keys(&self.name)?;
+        inner.backend.clear(&self.name)?;
+        for key in keys {inner.changed(&self.name, &key, None);}
+        Ok(())
+    }
+
+    ///Changes to the value under key
+    pub fn watch<F: Field + 'static>(&self, key: &str) -> Watch<F> {
+        Watch{cache: self.cache.clone(), changes: self.cache.subscribe(), namespace: self.name.clone(), key: Some(key.to_string()), _p: PhantomData}
+    }
+
+    ///Changes to every key in the namespace
+    pub fn watch_all<F: Field + 'static>(&self) -> Watch<F> {
+        Watch{cache: self.cache.clone(), changes: self.cache.subscribe(), namespace: self.name.clone(), key: None, _p: PhantomData}
+    }
+
+    async fn entries<F: Field + 'static>(&self) -> Result<Vec<(String, F)>, CacheError> {
+        let mut inner = self.cache.inner();
+        self.expire(&mut inner)?;
+        let entries = inner.entries(&self.name)?;
+        entries.into_iter().map(|(key, entry)| {
+            let item = self.read(&mut inner, &key, &entry)?;
+            Ok((key, item))
+        }).collect()
+    }
+}
+
+///Namespace of values of a single type F, keyed by id
+#[derive(Debug)]
+pub struct Table<F>(Namespace, PhantomData<fn() -> F>);
+
+impl<F> Clone for Table<F> {
+    fn clone(&self) -> Self {Table(self.0.clone(), PhantomData)}
+}
+
+impl<F: Field + 'static> Table<F> {
+    pub async fn set(&self, key: &str, item: &F) -> Result<(), CacheError> {self.0.set(key, item).await}
+    pub async fn set_expiring(&self, key: &str, item: &F, ttl: Duration) -> Result<(), CacheError> {
+        self.0.set_expiring(key, item, ttl).await
+    }
+    pub async fn get(&self, key: &str) -> Result<Option<F>, CacheError> {self.0.get(key).await}
+    pub async fn contains(&self, key: &str) -> Result<bool, CacheError> {self.0.contains(key).await}
+    pub async fn remove(&self, key: &str) -> Result<bool, CacheError> {self.0.remove(key).await}
+    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {self.0.keys().await}
+    pub async fn clear(&self) -> Result<(), CacheError> {self.0.clear().await}
+    pub fn watch(&self, key: &str) -> Watch<F> {self.0.watch(key)}
+    pub fn watch_all(&self) -> Watch<F> {self.0.watch_all()}
+
+    ///Every value in the table with its key, in key order
+    pub async fn entries(&self) -> Result<Vec<(String, F)>, CacheError> {self.0.entries().await}
+}
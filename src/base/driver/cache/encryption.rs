@@ -0,0 +1,88 @@
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, AeadCore};
+use chacha20poly1305::aead::{Aead, OsRng, Payload, rand_core::RngCore};
+
+use crate::base::driver::schema::{HEADER, ENCRYPTED};
+
+///Namespace of the salt for deriving keys from a passphrase, never encrypted nor evicted
+pub(super) const KEYS: &str = "rust_on_rails::encryption";
+
+///PBKDF2-HMAC-SHA256 rounds when deriving a key from a passphrase
+const ROUNDS: u32 = 600_000;
+
+const NONCE: usize = 12;
+
+///256 bit key the Cache encrypts its values with
+#[derive(Clone, PartialEq, Eq)]
+pub struct CacheKey([u8; 32]);
+
+impl std::fmt::Debug for CacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {write!(f, "CacheKey(..)")}
+}
+
+impl CacheKey {
+    pub fn new(key: [u8; 32]) -> Self {CacheKey(key)}
+
+    ///New random key, to be kept in the platform keychain
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        CacheKey(key)
+    }
+
+    ///Derives the key from a passphrase with PBKDF2, salted with Cache::salt
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+        CacheKey(pbkdf2::pbkdf2_hmac_array::<sha2::Sha256, 32>(passphrase.as_bytes(), salt, ROUNDS))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {&self.0}
+
+    fn cipher(&self) -> ChaCha20Poly1305 {ChaCha20Poly1305::new(Key::from_slice(&self.0))}
+}
+
+///Random salt for CacheKey::from_passphrase
+pub(super) fn salt() -> Vec<u8> {
+    let mut salt = vec![0; 16];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+///The key values are written with and the keys they were written with before
+#[derive(Debug, Default)]
+pub(super) struct Keys {
+    current: Option<CacheKey>,
+    previous: Vec<CacheKey>,
+}
+
+impl Keys {
+    ///Writes with the key from now on, keeping the one it replaces to read older values
+    pub(super) fn set(&mut self, key: Option<CacheKey>) {
+        if let Some(current) = std::mem::replace(&mut self.current, key) {
+            if !self.previous.contains(&current) {self.previous.push(current);}
+        }
+        if let Some(key) = &self.current {self.previous.retain(|previous| previous != key);}
+    }
+
+    ///Value bound to its namespace and key, so it can't be moved to another one
+    fn aad(namespace: &str, key: &str) -> Vec<u8> {[namespace.as_bytes(), &[0], key.as_bytes()].concat()}
+
+    ///Encrypts the value with the current key, headed by the encrypted flag and the nonce
+    pub(super) fn seal(&self, namespace: &str, key: &str, value: Vec<u8>) -> Vec<u8> {
+        let Some(current) = self.current.as_ref().filter(|_| namespace != KEYS) else {return value;};
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = current.cipher().encrypt(&nonce, Payload{msg: &value, aad: &Self::aad(namespace, key)}).unwrap();
+        [&[HEADER | ENCRYPTED][..], &nonce, &sealed].concat()
+    }
+
+    ///Decrypts the value with whichever key sealed it, returning whether it is stored as the current
+    ///key would store it, or None if none of the keys did
+    pub(super) fn open(&self, namespace: &str, key: &str, value: Vec<u8>) -> Option<(Vec<u8>, bool)> {
+        let encrypted = value.first() == Some(&(HEADER | ENCRYPTED)) && value.len() > NONCE;
+        if !encrypted {return Some((value, self.current.is_none() || namespace == KEYS));}
+        let (nonce, sealed) = value[1..].split_at(NONCE);
+        let aad = Self::aad(namespace, key);
+        self.current.iter().chain(&self.previous).enumerate().find_map(|(i, cipher)| {
+            let value = cipher.cipher().decrypt(Nonce::from_slice(nonce), Payload{msg: sealed, aad: &aad}).ok()?;
+            Some((value, i == 0 && self.current.is_some()))
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use super::{Backend, CacheError, Entry, Op, Usage};
+
+///An Entry as written to the namespace file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stored {
+    value: String,
+    expires: Option<u64>,
+    accessed: u64,
+    #[serde(default)]
+    version: u32,
+}
+
+impl From<Entry> for Stored {
+    fn from(entry: Entry) -> Self {
+        Stored{value: hex::encode(entry.value), expires: entry.expires, accessed: entry.accessed, version: entry.version}
+    }
+}
+
+type Entries = BTreeMap<String, Stored>;
+
+///Stores each namespace as a JSON file of hex encoded values, rewritten on every change
+#[derive(Debug)]
+pub struct FileBackend {
+    dir: PathBuf,
+    loaded: HashMap<String, Entries>,
+}
+
+impl FileBackend {
+    pub fn open(dir: PathBuf) -> Result<Self, CacheError> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileBackend{dir, loaded: HashMap::new()})
+    }
+
+    ///File of a namespace, hex encoding the name so any namespace is a valid file name
+    fn path(&self, namespace: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", hex::encode(namespace)))
+    }
+
+    ///Namespaces with a file in the directory
+    fn namespaces(&self) -> Result<Vec<String>, CacheError> {
+        let mut namespaces = Vec::new();
+        for file in std::fs::read_dir(&self.dir)? {
+            let name = file?.file_name().to_string_lossy().to_string();
+            let namespace = name.strip_suffix(".json").and_then(|name| hex::decode(name).ok())
+                .and_then(|name| String::from_utf8(name).ok());
+            namespaces.extend(namespace);
+        }
+        Ok(namespaces)
+    }
+
+    fn load(&mut self, namespace: &str) -> Result<&mut Entries, CacheError> {
+        if !self.loaded.contains_key(namespace) {
+            let entries = match std::fs::read(self.path(namespace)) {
+                Ok(bytes) => serde_json::from_slice(&bytes).map_err(std::io::Error::from)?,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => Entries::new(),
+                Err(error) => return Err(error.into())
+            };
+            self.loaded.insert(namespace.to_string(), entries);
+        }
+        Ok(self.loaded.get_mut(namespace).unwrap())
+    }
+
+    ///Writes the entries of a namespace to a temporary file, returning the file it replaces
+    ///and the temporary file, or None when the namespace is empty and its file should be removed
+    fn stage(&self, namespace: &str, entries: &Entries) -> Result<(PathBuf, Option<PathBuf>), CacheError> {
+        let path = self.path(namespace);
+        if entries.is_empty() {return Ok((path, None));}
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec(entries).map_err(std::io::Error::from)?)?;
+        Ok((path, Some(temp)))
+    }
+
+    fn replace((path, temp): (PathBuf, Option<PathBuf>)) -> Result<(), CacheError> {
+        match temp {
+            Some(temp) => std::fs::rename(temp, path)?,
+            None => if path.exists() {std::fs::remove_file(path)?;}
+        }
+        Ok(())
+    }
+
+    ///Writes the namespace to a temporary file and renames it into place
+    fn save(&self, namespace: &str) -> Result<(), CacheError> {
+        let empty = Entries::new();
+        Self::replace(self.stage(namespace, self.loaded.get(namespace).unwrap_or(&empty))?)
+    }
+
+    fn decode(namespace: &str, key: &str, stored: &Stored) -> Result<Entry, CacheError> {
+        let value = hex::decode(&stored.value).map_err(|error| CacheError::Decode{
+            namespace: namespace.to_string(), key: key.to_string(), error: error.to_string()
+        })?;
+        Ok(Entry{value, expires: stored.expires, accessed: stored.accessed, version: stored.version})
+    }
+}
+
+impl Backend for FileBackend {
+    fn get(&mut self, namespace: &str, key: &str) -> Result<Option<Entry>, CacheError> {
+        self.load(namespace)?.get(key).map(|stored| Self::decode(namespace, key, stored)).transpose()
+    }
+
+    fn set(&mut self, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError> {
+        self.load(namespace)?.insert(key.to_string(), entry.into());
+        self.save(namespace)
+    }
+
+    fn touch(&mut self, namespace: &str, key: &str, accessed: u64) -> Result<(), CacheError> {
+        let Some(stored) = self.load(namespace)?.get_mut(key) else {return Ok(());};
+        stored.accessed = accessed;
+        self.save(namespace)
+    }
+
+    fn remove(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        let removed = self.load(namespace)?.remove(key).is_some();
+        if removed {self.save(namespace)?;}
+        Ok(removed)
+    }
+
+    fn keys(&mut self, namespace: &str) -> Result<Vec<String>, CacheError> {
+        Ok(self.load(namespace)?.keys().cloned().collect())
+    }
+
+    fn entries(&mut self, namespace: &str) -> Result<Vec<(String, Entry)>, CacheError> {
+        self.load(namespace)?.iter().map(|(key, stored)| Ok((key.clone(), Self::decode(namespace, key, stored)?))).collect()
+    }
+
+    fn clear(&mut self, namespace: &str) -> Result<(), CacheError> {
+        self.load(namespace)?.clear();
+        self.save(namespace)
+    }
+
+    fn expire(&mut self, namespace: &str, now: u64) -> Result<Vec<String>, CacheError> {
+        let entries = self.load(namespace)?;
+        let expired = entries.iter().filter(|(_, stored)| stored.expires.is_some_and(|expires| expires <= now))
+            .map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        for key in &expired {entries.remove(key);}
+        if !expired.is_empty() {self.save(namespace)?;}
+        Ok(expired)
+    }
+
+    fn usage(&mut self) -> Result<Vec<Usage>, CacheError> {
+        let mut usage = Vec::new();
+        for namespace in self.namespaces()? {
+            usage.extend(self.load(&namespace)?.iter().map(|(key, stored)| Usage{
+                namespace: namespace.clone(),
+                key: key.clone(),
+                size: (key.len() + stored.value.len()) as u64,
+                expires: stored.expires,
+                accessed: stored.accessed,
+            }));
+        }
+        Ok(usage)
+    }
+
+    ///Stages every touched namespace before replacing any file, so a failed write changes nothing;
+    ///the renames that follow are not atomic across namespaces
+    fn apply(&mut self, ops: Vec<Op>) -> Result<(), CacheError> {
+        let mut staged = HashMap::<String, Entries>::new();
+        for op in ops {
+            let namespace = match &op {Op::Set{namespace, ..} | Op::Remove{namespace, ..} => namespace.clone()};
+            if !staged.contains_key(&namespace) {
+                let entries = self.load(&namespace)?.clone();
+                staged.insert(namespace.clone(), entries);
+            }
+            let entries = staged.get_mut(&namespace).unwrap();
+            match op {
+                Op::Set{key, entry, ..} => {entries.insert(key, entry.into());},
+                Op::Remove{key, ..} => {entries.remove(&key);}
+            }
+        }
+        let files = staged.iter().map(|(namespace, entries)| self.stage(namespace, entries)).collect::<Result<Vec<_>, _>>()?;
+        for file in files {Self::replace(file)?;}
+        self.loaded.extend(staged);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{Backend, CacheError, Entry, Usage};
+
+///Keeps every namespace in memory, lost when the app closes, for tests of tasks and plugins
+#[derive(Debug, Default)]
+pub struct MemoryBackend(HashMap<String, BTreeMap<String, Entry>>);
+
+impl Backend for MemoryBackend {
+    fn get(&mut self, namespace: &str, key: &str) -> Result<Option<Entry>, CacheError> {
+        Ok(self.0.get(namespace).and_then(|entries| entries.get(key)).cloned())
+    }
+
+    fn set(&mut self, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError> {
+        self.0.entry(namespace.to_string()).or_default().insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    fn touch(&mut self, namespace: &str, key: &str, accessed: u64) -> Result<(), CacheError> {
+        if let Some(entry) = self.0.get_mut(namespace).and_then(|entries| entries.get_mut(key)) {
+            entry.accessed = accessed;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        Ok(self.0.get_mut(namespace).and_then(|entries| entries.remove(key)).is_some())
+    }
+
+    fn keys(&mut self, namespace: &str) -> Result<Vec<String>, CacheError> {
+        Ok(self.0.get(namespace).map(|entries| entries.keys().cloned().collect()).unwrap_or_default())
+    }
+
+    fn entries(&mut self, namespace: &str) -> Result<Vec<(String, Entry)>, CacheError> {
+        Ok(self.0.get(namespace).map(|entries| entries.clone().into_iter().collect()).unwrap_or_default())
+    }
+
+    fn clear(&mut self, namespace: &str) -> Result<(), CacheError> {
+        self.0.remove(namespace);
+        Ok(())
+    }
+
+    fn expire(&mut self, namespace: &str, now: u64) -> Result<Vec<String>, CacheError> {
+        let Some(entries) = self.0.get_mut(namespace) else {return Ok(Vec::new());};
+        let expired = entries.iter().filter(|(_, entry)| entry.expired(now)).map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        for key in &expired {entries.remove(key);}
+        Ok(expired)
+    }
+
+    fn usage(&mut self) -> Result<Vec<Usage>, CacheError> {
+        Ok(self.0.iter().flat_map(|(namespace, entries)| entries.iter().map(|(key, entry)| Usage{
+            namespace: namespace.clone(),
+            key: key.clone(),
+            size: (key.len() + entry.value.len()) as u64,
+            expires: entry.expires,
+            accessed: entry.accessed,
+        })).collect())
+    }
+}
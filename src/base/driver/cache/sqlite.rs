@@ -0,0 +1,313 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::{Backend, CacheError, Entry, Op, Usage, DEFAULT};
+
+///Schema version stored in the sqlite user_version pragma
+const VERSION: i64 = 5;
+
+///Rows kept in the changes table for other connections to read, the oldest being removed past this
+const CHANGES_KEPT: i64 = 1024;
+
+///Connections opened by this process, telling its writers apart from those of other processes
+static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+///Stores every namespace in a single sqlite database, values as blobs
+///
+///Every key set or removed is also logged with the connection that wrote it, so a connection
+///can tell which keys the other desktop process changed.
+#[derive(Debug)]
+pub struct SqliteBackend {
+    db: rusqlite::Connection,
+    ///Id of this connection in the changes it logs
+    writer: i64,
+    ///Last change read from the log
+    seen: i64,
+    ///Data version when the log was last read, which only moves on commits of other connections
+    data_version: i64,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> Result<Self, CacheError> {
+        if let Some(dir) = path.parent() {std::fs::create_dir_all(dir)?;}
+        let db = rusqlite::Connection::open(path)?;
+        Self::migrate(&db)?;
+        let writer = (i64::from(std::process::id()) << 32) | i64::from(CONNECTIONS.fetch_add(1, Ordering::Relaxed));
+        let seen = db.query_row("SELECT coalesce(max(seq), 0) FROM changes;", [], |row| row.get(0))?;
+        let data_version = db.query_row("PRAGMA data_version;", [], |row| row.get(0))?;
+        Ok(SqliteBackend{db, writer, seen, data_version})
+    }
+
+    ///Opens the database, moving a corrupt one aside and starting over rather than failing
+    ///
+    ///Any other error, such as the database being locked by the other process, is returned
+    ///and the file is left alone.
+    pub fn open_or_recover(path: &Path) -> Result<Self, CacheError> {
+        Self::open(path).or_else(|error| {
+            if !Self::is_corrupt(&error) {return Err(error);}
+            log::error!("Could not open {}, starting with an empty cache: {}", path.display(), error);
+            Self::set_aside(path);
+            Self::open(path)
+        })
+    }
+
+    fn is_corrupt(error: &CacheError) -> bool {
+        matches!(error, CacheError::Sqlite(rusqlite::Error::SqliteFailure(error, _)) if matches!(
+            error.code, rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase
+        ))
+    }
+
+    fn set_aside(path: &Path) {
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let file = path.with_file_name(format!("{}{}", path.file_name().unwrap().to_string_lossy(), suffix));
+            if file.exists() {
+                let _ = std::fs::rename(&file, file.with_file_name(format!(
+                    "{}.corrupt-{}", file.file_name().unwrap().to_string_lossy(), time
+                )));
+            }
+        }
+    }
+
+    ///Brings the schema up to VERSION one step at a time
+    ///
+    ///Version 1 moved the values of the single kvs table into the default namespace,
+    ///version 2 added the expiry and access times, version 3 the schema version of each value
+    ///version 4 turned the hex text values into blobs and version 5 added the log of changes.
+    ///
+    ///Each step runs under an immediate transaction and checks the version again once it holds the
+    ///write lock, so the UI and background processes opening an old database together don't both
+    ///apply it.
+    fn migrate(db: &rusqlite::Connection) -> Result<(), CacheError> {
+        if Self::version(db)? >= VERSION {return Ok(());}
+        for step in 1..=VERSION {
+            db.execute_batch("BEGIN IMMEDIATE;")?;
+            let migrated = Self::step(db, step);
+            db.execute_batch(if migrated.is_ok() {"COMMIT;"} else {"ROLLBACK;"})?;
+            migrated?;
+        }
+        Ok(())
+    }
+
+    fn version(db: &rusqlite::Connection) -> Result<i64, CacheError> {
+        Ok(db.query_row("PRAGMA user_version;", [], |row| row.get(0))?)
+    }
+
+    ///Brings the schema from the version before step to step, unless it is already there
+    fn step(db: &rusqlite::Connection, step: i64) -> Result<(), CacheError> {
+        if Self::version(db)? >= step {return Ok(());}
+        match step {
+            1 => db.execute_batch(&format!("
+                CREATE TABLE if not exists entries(
+                    namespace TEXT NOT NULL, key TEXT NOT NULL, value TEXT, PRIMARY KEY(namespace, key)
+                );
+                CREATE TABLE if not exists kvs(key TEXT NOT NULL UNIQUE, value TEXT);
+                INSERT OR IGNORE INTO entries(namespace, key, value) SELECT '{DEFAULT}', key, value FROM kvs;
+                DROP TABLE kvs;
+            "))?,
+            2 => db.execute_batch("
+                ALTER TABLE entries ADD COLUMN expires INTEGER;
+                ALTER TABLE entries ADD COLUMN accessed INTEGER NOT NULL DEFAULT 0;
+                CREATE INDEX if not exists entries_expires ON entries(expires) WHERE expires IS NOT NULL;
+            ")?,
+            3 => db.execute_batch("
+                ALTER TABLE entries ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+            ")?,
+            4 => db.execute_batch("
+                UPDATE entries SET value = unhex(value) WHERE typeof(value) = 'text' AND unhex(value) IS NOT NULL;
+            ")?,
+            _ => db.execute_batch("
+                CREATE TABLE changes(
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT, namespace TEXT NOT NULL, key TEXT NOT NULL, writer INTEGER NOT NULL
+                );
+            ")?
+        }
+        db.execute_batch(&format!("PRAGMA user_version = {step};"))?;
+        Ok(())
+    }
+
+    fn decode(namespace: &str, key: &str, value: rusqlite::types::ValueRef) -> Result<Vec<u8>, CacheError> {
+        match value {
+            rusqlite::types::ValueRef::Blob(value) => Ok(value.to_vec()),
+            value => Err(CacheError::Decode{
+                namespace: namespace.to_string(), key: key.to_string(), error: format!("{} value is not a blob", value.data_type())
+            })
+        }
+    }
+
+    ///Logs that the key was set or removed by the writer, dropping the oldest changes past CHANGES_KEPT
+    fn log(db: &rusqlite::Connection, writer: i64, namespace: &str, key: &str) -> Result<(), CacheError> {
+        let mut stmt = db.prepare_cached("INSERT INTO changes(namespace, key, writer) VALUES (?1, ?2, ?3);")?;
+        stmt.execute(rusqlite::params![namespace, key, writer])?;
+        let mut stmt = db.prepare_cached("DELETE FROM changes WHERE seq <= ?1;")?;
+        stmt.execute([db.last_insert_rowid() - CHANGES_KEPT])?;
+        Ok(())
+    }
+
+    fn write(db: &rusqlite::Connection, writer: i64, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError> {
+        let mut stmt = db.prepare_cached(
+            "INSERT INTO entries(namespace, key, value, expires, accessed, version) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(namespace, key) DO UPDATE
+             SET value=excluded.value, expires=excluded.expires, accessed=excluded.accessed, version=excluded.version;"
+        )?;
+        stmt.execute(rusqlite::params![
+            namespace, key, entry.value, entry.expires.map(|expires| expires as i64), entry.accessed as i64,
+            entry.version
+        ])?;
+        Self::log(db, writer, namespace, key)
+    }
+
+    fn delete(db: &rusqlite::Connection, writer: i64, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        let mut stmt = db.prepare_cached("DELETE FROM entries WHERE namespace = ?1 AND key = ?2;")?;
+        let removed = stmt.execute([namespace, key])? > 0;
+        if removed {Self::log(db, writer, namespace, key)?;}
+        Ok(removed)
+    }
+
+    fn entry(namespace: &str, key: &str, row: &rusqlite::Row, first: usize) -> Result<Entry, CacheError> {
+        Ok(Entry{
+            value: Self::decode(namespace, key, row.get_ref(first)?)?,
+            expires: row.get::<_, Option<i64>>(first + 1)?.map(|expires| expires as u64),
+            accessed: row.get::<_, i64>(first + 2)? as u64,
+            version: row.get(first + 3)?,
+        })
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn get(&mut self, namespace: &str, key: &str) -> Result<Option<Entry>, CacheError> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT value, expires, accessed, version FROM entries WHERE namespace = ?1 AND key = ?2;"
+        )?;
+        let mut rows = stmt.query([namespace, key])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(Self::entry(namespace, key, row, 0)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn set(&mut self, namespace: &str, key: &str, entry: Entry) -> Result<(), CacheError> {
+        Self::write(&self.db, self.writer, namespace, key, entry)
+    }
+
+    fn touch(&mut self, namespace: &str, key: &str, accessed: u64) -> Result<(), CacheError> {
+        let mut stmt = self.db.prepare_cached("UPDATE entries SET accessed = ?3 WHERE namespace = ?1 AND key = ?2;")?;
+        stmt.execute(rusqlite::params![namespace, key, accessed as i64])?;
+        Ok(())
+    }
+
+    fn contains(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        let mut stmt = self.db.prepare_cached("SELECT 1 FROM entries WHERE namespace = ?1 AND key = ?2;")?;
+        Ok(stmt.exists([namespace, key])?)
+    }
+
+    fn remove(&mut self, namespace: &str, key: &str) -> Result<bool, CacheError> {
+        Self::delete(&self.db, self.writer, namespace, key)
+    }
+
+    fn keys(&mut self, namespace: &str) -> Result<Vec<String>, CacheError> {
+        let mut stmt = self.db.prepare_cached("SELECT key FROM entries WHERE namespace = ?1 ORDER BY key;")?;
+        let keys = stmt.query_map([namespace], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        Ok(keys)
+    }
+
+    fn entries(&mut self, namespace: &str) -> Result<Vec<(String, Entry)>, CacheError> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT key, value, expires, accessed, version FROM entries WHERE namespace = ?1 ORDER BY key;"
+        )?;
+        let mut rows = stmt.query([namespace])?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            let key: String = row.get(0)?;
+            let entry = Self::entry(namespace, &key, row, 1)?;
+            entries.push((key, entry));
+        }
+        Ok(entries)
+    }
+
+    fn clear(&mut self, namespace: &str) -> Result<(), CacheError> {
+        let tx = self.db.transaction()?;
+        let keys = tx.prepare_cached("DELETE FROM entries WHERE namespace = ?1 RETURNING key;")?
+            .query_map([namespace], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        for key in keys {Self::log(&tx, self.writer, namespace, &key)?;}
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn expire(&mut self, namespace: &str, now: u64) -> Result<Vec<String>, CacheError> {
+        let tx = self.db.transaction()?;
+        let keys = tx.prepare_cached("DELETE FROM entries WHERE namespace = ?1 AND expires <= ?2 RETURNING key;")?
+            .query_map(rusqlite::params![namespace, now as i64], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        for key in &keys {Self::log(&tx, self.writer, namespace, key)?;}
+        tx.commit()?;
+        Ok(keys)
+    }
+
+    fn usage(&mut self) -> Result<Vec<Usage>, CacheError> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT namespace, key, length(key) + length(value), expires, accessed FROM entries;"
+        )?;
+        let usage = stmt.query_map([], |row| Ok(Usage{
+            namespace: row.get(0)?,
+            key: row.get(1)?,
+            size: row.get::<_, Option<i64>>(2)?.unwrap_or_default() as u64,
+            expires: row.get::<_, Option<i64>>(3)?.map(|expires| expires as u64),
+            accessed: row.get::<_, i64>(4)? as u64,
+        }))?.collect::<Result<Vec<_>, _>>()?;
+        Ok(usage)
+    }
+
+    ///Applies the operations inside one sqlite transaction, rolled back if any of them fails
+    fn apply(&mut self, ops: Vec<Op>) -> Result<(), CacheError> {
+        let tx = self.db.transaction()?;
+        for op in ops {
+            match op {
+                Op::Set{namespace, key, entry} => Self::write(&tx, self.writer, &namespace, &key, entry)?,
+                Op::Remove{namespace, key} => {Self::delete(&tx, self.writer, &namespace, &key)?;}
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    ///Reads the keys logged by other connections since the last call, once they committed anything
+    fn external(&mut self) -> Result<Vec<(String, String)>, CacheError> {
+        let data_version = self.db.query_row("PRAGMA data_version;", [], |row| row.get(0))?;
+        if data_version == self.data_version {return Ok(Vec::new());}
+        self.data_version = data_version;
+        let mut stmt = self.db.prepare_cached("SELECT seq, namespace, key, writer FROM changes WHERE seq > ?1 ORDER BY seq;")?;
+        let mut rows = stmt.query([self.seen])?;
+        let mut keys = Vec::new();
+        while let Some(row) = rows.next()? {
+            self.seen = row.get(0)?;
+            if row.get::<_, i64>(3)? != self.writer {keys.push((row.get(1)?, row.get(2)?));}
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Cache;
+
+    #[tokio::test]
+    async fn watches_other_connections() {
+        let dir = std::env::temp_dir().join(format!("rust_on_rails_shared_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let ui = Cache::new(SqliteBackend::open(&dir.join("cache.db")).unwrap());
+        let background = Cache::new(SqliteBackend::open(&dir.join("cache.db")).unwrap());
+        let mut watch = ui.namespace("sync").watch::<u32>("progress");
+        let mut own = background.namespace("sync").watch::<u32>("progress");
+
+        background.namespace("sync").set("progress", &5).await.unwrap();
+        let changed = tokio::time::timeout(std::time::Duration::from_secs(2), watch.next()).await.unwrap();
+        assert_eq!(changed.value, Some(5));
+
+        //The writer is only notified once, by its own broadcast
+        assert_eq!(own.try_next().unwrap().value, Some(5));
+        tokio::time::sleep(super::super::POLL_EVERY).await;
+        assert!(own.try_next().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use super::{Cache, CacheError, Entry, Field, DEFAULT, PURGE_EVERY, now, expires_in};
+
+///A write queued in a Transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Set{namespace: String, key: String, entry: Entry},
+    Remove{namespace: String, key: String},
+}
+
+impl Op {
+    fn target(&self) -> (&str, &str) {
+        match self {
+            Op::Set{namespace, key, ..} | Op::Remove{namespace, key} => (namespace, key)
+        }
+    }
+}
+
+///Group of sets and removes that are written together on commit, or not at all
+///
+///Reads through the transaction see its own pending writes.
+#[derive(Debug)]
+pub struct Transaction {
+    cache: Cache,
+    ops: Vec<Op>,
+}
+
+impl Transaction {
+    pub(super) fn new(cache: Cache) -> Self {Transaction{cache, ops: Vec::new()}}
+
+    fn push(&mut self, namespace: &str, key: &str, entry: Option<Entry>) {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        self.ops.push(match entry {
+            Some(entry) => Op::Set{namespace, key, entry},
+            None => Op::Remove{namespace, key}
+        });
+    }
+
+    pub fn set<F: Field + 'static>(&mut self, item: &F) {self.set_in(DEFAULT, &F::ident(), item)}
+
+    ///Sets the value under key in the namespace
+    pub fn set_in<F: Field + 'static>(&mut self, namespace: &str, key: &str, item: &F) {
+        self.push(namespace, key, Some(Entry::new(item, None)));
+    }
+
+    pub fn set_expiring<F: Field + 'static>(&mut self, namespace: &str, key: &str, item: &F, ttl: Duration) {
+        self.push(namespace, key, Some(Entry::new(item, Some(expires_in(ttl)))));
+    }
+
+    pub fn remove<F: Field + 'static>(&mut self) {self.remove_in(DEFAULT, &F::ident())}
+
+    ///Removes the value under key in the namespace
+    pub fn remove_in(&mut self, namespace: &str, key: &str) {self.push(namespace, key, None);}
+
+    ///The value of type F as it will be once committed, or its default
+    pub async fn get<F: Field + 'static>(&self) -> Result<F, CacheError> {
+        Ok(self.get_in(DEFAULT, &F::ident()).await?.unwrap_or_default())
+    }
+
+    ///The value under key as it will be once committed
+    pub async fn get_in<F: Field + 'static>(&self, namespace: &str, key: &str) -> Result<Option<F>, CacheError> {
+        let namespace = self.cache.namespace(namespace);
+        match self.ops.iter().rev().find(|op| op.target() == (namespace.name(), key)) {
+            Some(Op::Set{entry, ..}) if !entry.expired(now()) => Ok(Some(namespace.decode(key, entry)?.0)),
+            Some(_) => Ok(None),
+            None => namespace.get(key).await
+        }
+    }
+
+    ///Writes every queued operation atomically, notifying watchers once they are stored
+    pub async fn commit(self) -> Result<(), CacheError> {
+        if self.ops.is_empty() {return Ok(());}
+        let mut inner = self.cache.inner();
+        inner.apply(self.ops)?;
+        inner.writes += 1;
+        let purge = inner.writes >= PURGE_EVERY;
+        drop(inner);
+        if purge {self.cache.purge_after_write();}
+        Ok(())
+    }
+}
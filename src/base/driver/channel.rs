@@ -0,0 +1,49 @@
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+type Queues = HashMap<TypeId, VecDeque<Box<dyn Any + Send>>>;
+
+///Messages kept per type before the oldest are dropped, so a queue nobody takes from stays bounded
+pub(super) const QUEUED: usize = 256;
+
+///Typed message queues shared between the app and its Tasks, one queue per message type
+#[derive(Debug, Clone, Default)]
+pub struct Mailbox {
+    queues: Arc<(Mutex<Queues>, Notify)>,
+    ///Messages are dropped as they are sent, in a process where nothing takes them
+    discard: bool,
+}
+
+impl Mailbox {
+    ///Mailbox dropping every message sent to it
+    pub fn discarding() -> Self {Mailbox{queues: Arc::default(), discard: true}}
+
+    pub fn send<M: Send + 'static>(&self, message: M) {
+        if self.discard {return;}
+        let mut queues = self.queues.0.lock().unwrap();
+        let queue = queues.entry(TypeId::of::<M>()).or_default();
+        if queue.len() >= QUEUED {
+            queue.pop_front();
+            log::warn!("Dropped the oldest {} message, none were taken", std::any::type_name::<M>());
+        }
+        queue.push_back(Box::new(message));
+        drop(queues);
+        self.queues.1.notify_waiters();
+    }
+
+    pub fn receive<M: Send + 'static>(&self) -> Option<M> {
+        self.queues.0.lock().unwrap().get_mut(&TypeId::of::<M>())?.pop_front()
+            .map(|message| *message.downcast::<M>().unwrap())
+    }
+
+    pub async fn next<M: Send + 'static>(&self) -> M {
+        loop {
+            let notified = self.queues.1.notified();
+            if let Some(message) = self.receive() {return message;}
+            notified.await;
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::sync::oneshot;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+///Source of time for the Runtime and its Tasks
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn system_time(&self) -> SystemTime;
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+///Real time, sleeping on the tokio timer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {Instant::now()}
+    fn system_time(&self) -> SystemTime {SystemTime::now()}
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+#[derive(Debug)]
+struct Manual {
+    start: (Instant, SystemTime),
+    now: Instant,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+///Clock that only moves when advanced, for deterministic tests of Tasks
+///
+///Use it with a current thread tokio runtime so that woken tasks run in the order of their deadlines.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<Manual>>);
+
+impl ManualClock {
+    pub fn new() -> Self {Self::at(SystemTime::now())}
+
+    ///Starts the clock at the given wall-clock time
+    pub fn at(time: SystemTime) -> Self {
+        let now = Instant::now();
+        ManualClock(Arc::new(Mutex::new(Manual{start: (now, time), now, sleepers: Vec::new()})))
+    }
+
+    ///Moves time forward, waking each sleeper in deadline order and yielding so it can run
+    pub async fn advance(&self, duration: Duration) {
+        let target = self.now() + duration;
+        loop {
+            let sleeper = {
+                let mut manual = self.0.lock().unwrap();
+                manual.sleepers.retain(|(_, sender)| !sender.is_closed());
+                let next = manual.sleepers.iter().enumerate()
+                    .filter(|(_, (deadline, _))| *deadline <= target)
+                    .min_by_key(|(_, (deadline, _))| *deadline).map(|(i, _)| i);
+                match next {
+                    Some(i) => {
+                        let (deadline, sender) = manual.sleepers.remove(i);
+                        manual.now = manual.now.max(deadline);
+                        Some(sender)
+                    },
+                    None => {manual.now = target; None}
+                }
+            };
+            match sleeper {
+                Some(sender) => {
+                    let _ = sender.send(());
+                    tokio::task::yield_now().await;
+                },
+                None => return
+            }
+        }
+    }
+
+    ///Number of tasks currently waiting on the clock
+    pub fn sleepers(&self) -> usize {
+        self.0.lock().unwrap().sleepers.iter().filter(|(_, sender)| !sender.is_closed()).count()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {Self::new()}
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {self.0.lock().unwrap().now}
+    fn system_time(&self) -> SystemTime {
+        let manual = self.0.lock().unwrap();
+        manual.start.1 + (manual.now - manual.start.0)
+    }
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let mut manual = self.0.lock().unwrap();
+        if deadline <= manual.now {return Box::pin(std::future::ready(()));}
+        let (sender, receiver) = oneshot::channel();
+        manual.sleepers.push((deadline, sender));
+        Box::pin(async move {let _ = receiver.await;})
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+///A Cache entry as written to a Dump
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DumpedEntry {
+    ///The decoded value, null if it could not be decoded
+    pub value: Value,
+    ///Hex of the stored bytes when they could not be decoded, loaded back as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+///Cache entries by namespace and key
+pub type CacheDump = BTreeMap<String, BTreeMap<String, DumpedEntry>>;
+
+///Human readable contents of the State and Cache, to attach to a bug report and load back
+///to reproduce it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    ///State values by Field ident
+    pub state: BTreeMap<String, Value>,
+    pub cache: CacheDump,
+}
+
+impl Dump {
+    pub fn to_json(&self) -> String {serde_json::to_string_pretty(self).unwrap()}
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {serde_json::from_str(json)}
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde::{Serialize, Deserialize};
+use tokio::sync::{broadcast, watch, Notify};
+
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::runtime::Handle;
+
+use super::state::Field;
+use super::channel::QUEUED;
+
+#[cfg(unix)]
+const SOCKET: &str = "background.sock";
+#[cfg(unix)]
+const RECONNECT: Duration = Duration::from_secs(1);
+#[cfg(unix)]
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+const BUFFER: usize = 256;
+
+type Inbox = HashMap<String, VecDeque<Vec<u8>>>;
+
+///A single message on the socket, one JSON object per line
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    ident: String,
+    value: String,
+}
+
+///Channel between the desktop background process and the UI process
+///
+///The background process listens on a Unix socket in the storage path and the UI process keeps
+///connecting to it, so either side can start first. Messages are Fields matched by their ident.
+#[derive(Debug, Clone)]
+pub struct Ipc {
+    inbox: Arc<(Mutex<Inbox>, Notify)>,
+    outgoing: broadcast::Sender<String>,
+    peers: Arc<watch::Sender<usize>>,
+}
+
+impl Ipc {
+    fn new() -> Self {
+        Ipc{
+            inbox: Arc::default(),
+            outgoing: broadcast::channel(BUFFER).0,
+            peers: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    ///Listens for UI processes, replacing a socket left behind by a background process that exited
+    #[cfg(unix)]
+    pub(crate) fn serve(storage_path: &Path, handle: &Handle) -> Self {
+        let ipc = Self::new();
+        let path = storage_path.join(SOCKET);
+        let server = ipc.clone();
+        handle.spawn(async move {
+            if UnixStream::connect(&path).await.is_ok() {
+                return log::error!("Another background process is listening on {}", path.display());
+            }
+            let _ = std::fs::remove_file(&path);
+            let listener = match UnixListener::bind(&path) {
+                Ok(listener) => listener,
+                Err(error) => return log::error!("Could not listen on {}: {}", path.display(), error)
+            };
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {tokio::spawn(server.clone().connection(stream));},
+                    Err(error) => log::warn!("Could not accept IPC connection: {}", error)
+                }
+            }
+        });
+        ipc
+    }
+
+    ///Connects to the background process, reconnecting whenever it is started again
+    ///
+    ///Failed attempts back off exponentially up to a minute apart, so an app without a running
+    ///background process doesn't keep polling for one.
+    #[cfg(unix)]
+    pub(crate) fn connect(storage_path: &Path, handle: &Handle) -> Self {
+        let ipc = Self::new();
+        let path = storage_path.join(SOCKET);
+        let client = ipc.clone();
+        handle.spawn(async move {
+            let mut delay = RECONNECT;
+            loop {
+                if let Ok(stream) = UnixStream::connect(&path).await {
+                    client.clone().connection(stream).await;
+                    delay = RECONNECT;
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(RECONNECT_MAX);
+            }
+        });
+        ipc
+    }
+
+    #[cfg(unix)]
+    async fn connection(self, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut outgoing = self.outgoing.subscribe();
+        self.peers.send_modify(|peers| *peers += 1);
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => self.deliver(&line),
+                    _ => break
+                },
+                message = outgoing.recv() => match message {
+                    Ok(message) => if writer.write_all(message.as_bytes()).await.is_err() {break;},
+                    Err(broadcast::error::RecvError::Lagged(missed)) => log::warn!("IPC peer missed {} messages", missed),
+                    Err(broadcast::error::RecvError::Closed) => break
+                }
+            }
+        }
+        self.peers.send_modify(|peers| *peers -= 1);
+    }
+
+    fn deliver(&self, line: &str) {
+        let message = serde_json::from_str::<Envelope>(line).ok()
+            .and_then(|envelope| Some((envelope.ident, hex::decode(envelope.value).ok()?)));
+        let Some((ident, value)) = message else {return log::warn!("Dropped malformed IPC message");};
+        let mut inbox = self.inbox.0.lock().unwrap();
+        let queue = inbox.entry(ident.clone()).or_default();
+        if queue.len() >= QUEUED {
+            queue.pop_front();
+            log::warn!("Dropped the oldest IPC {} message, none were taken", ident);
+        }
+        queue.push_back(value);
+        drop(inbox);
+        self.inbox.1.notify_waiters();
+    }
+
+    ///Sends a message to every connected process, returning false if none is connected
+    pub fn send<F: Field + 'static>(&self, message: &F) -> bool {
+        let envelope = Envelope{ident: F::ident(), value: hex::encode(message.to_bytes())};
+        let mut line = serde_json::to_string(&envelope).unwrap();
+        line.push('\n');
+        self.outgoing.send(line).is_ok()
+    }
+
+    ///Takes the oldest message of type F received from the other process, dropping those that
+    ///can't be decoded as F
+    pub fn receive<F: Field + 'static>(&self) -> Option<F> {
+        let mut inbox = self.inbox.0.lock().unwrap();
+        let queue = inbox.get_mut(&F::ident())?;
+        while let Some(bytes) = queue.pop_front() {
+            match F::try_from_bytes(&bytes) {
+                Ok(message) => return Some(message),
+                Err(error) => log::warn!("Dropped IPC message that could not be decoded as {}: {}", F::ident(), error)
+            }
+        }
+        None
+    }
+
+    ///Waits for the next message of type F from the other process
+    pub async fn next<F: Field + 'static>(&self) -> F {
+        loop {
+            let notified = self.inbox.1.notified();
+            if let Some(message) = self.receive() {return message;}
+            notified.await;
+        }
+    }
+
+    ///Whether the other process is currently connected
+    pub fn is_connected(&self) -> bool {*self.peers.borrow() > 0}
+
+    ///Waits until the other process is connected
+    pub async fn connected(&self) {
+        let _ = self.peers.subscribe().wait_for(|peers| *peers > 0).await;
+    }
+}
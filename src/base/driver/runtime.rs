@@ -1,20 +1,123 @@
-use std::time::{Instant, Duration};
-use std::sync::mpsc::{TryRecvError, Sender, Receiver, channel};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 
 use crate::base::HeadlessContext;
+use super::clock::{Clock, SystemClock};
 
-const THREAD_TICK: u64 = 16;
+mod cron;
+pub use cron::{Cron, InvalidCron};
 
 pub use async_trait::async_trait;
 
+///Cache namespace holding the wall-clock time of the last run of each Cron task, keyed by
+///Task::name and the cron expression
+pub(super) const LAST_RUNS: &str = "rust_on_rails::last_runs";
+
+///Identifies each spawned task in the metrics
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+///Finished tasks whose metrics are kept, the oldest being dropped first
+const FINISHED: usize = 64;
+
+///How long Runtime::close waits for tasks to finish before stopping them
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+pub type TaskError = Box<dyn std::error::Error + Send + Sync>;
+
 #[async_trait]
 pub trait Task: Send {
-    fn interval(&self) -> Option<Duration>;
-    async fn run(&mut self, ctx: &mut HeadlessContext);
+    fn interval(&self) -> Option<Duration> {None}
+    ///When the task runs, defaults to every interval or never without one
+    fn schedule(&self) -> Schedule {
+        self.interval().map(Schedule::Every).unwrap_or(Schedule::Never)
+    }
+    async fn run(&mut self, ctx: &mut HeadlessContext) -> Result<(), TaskError>;
+
+    ///What the runtime does when run returns an error
+    fn retry(&self) -> RetryPolicy {RetryPolicy::Skip}
+    ///Names the task in logs and metrics, defaults to its type name
+    ///
+    ///Cron tasks should override it, as their last run time is kept under the name and the cron
+    ///expression, and is lost when the type is renamed or moved.
+    fn name(&self) -> String {std::any::type_name_of_val(self).to_string()}
+
+    ///Called once when the task is cancelled or the Runtime closes, within the shutdown grace period
+    async fn on_shutdown(&mut self, _ctx: &mut HeadlessContext) {}
+    ///Called when the app or the TaskHandle pauses the task
+    async fn on_pause(&mut self, _ctx: &mut HeadlessContext) {}
+    ///Called when the task is no longer paused
+    async fn on_resume(&mut self, _ctx: &mut HeadlessContext) {}
 }
 
 pub type Tasks = Vec<Box<dyn Task>>;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    ///Run every interval, starting one interval after the task is spawned
+    Every(Duration),
+    ///Run once as soon as the task is spawned
+    Once,
+    ///Run once after a delay
+    After(Duration),
+    ///Run at the wall-clock times matching the expression, a time missed while
+    ///the app was closed runs once at startup
+    Cron(Cron),
+    ///Run each time the app resumes, background tasks are never resumed
+    OnResume,
+    Never,
+}
+
+#[derive(Clone, Copy)]
+enum Due {
+    At(Instant),
+    Resume,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Wake {
+    Due,
+    Lifecycle,
+    Control,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryPolicy {
+    ///Log the failure and wait for the next interval
+    Skip,
+    ///Retry after initial, doubling the delay up to max, then give up until the next interval after attempts retries
+    Backoff{initial: Duration, max: Duration, attempts: u32},
+    ///Stop running the task after the first failure
+    Disable,
+}
+
+impl RetryPolicy {
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        match self {
+            RetryPolicy::Backoff{initial, max, attempts} if (1..=*attempts).contains(&failures) =>
+                Some(initial.saturating_mul(2u32.saturating_pow(failures - 1)).min(*max)),
+            _ => None
+        }
+    }
+}
+
+///Posted to the app whenever a Task run fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskFailed {
+    pub task: String,
+    pub error: String,
+    ///Consecutive failures including this one
+    pub attempt: u32,
+    ///The task will not run again
+    pub disabled: bool,
+}
+
 #[macro_export]
 macro_rules! tasks {
     [$($renderer:expr),+] => {
@@ -22,24 +125,326 @@ macro_rules! tasks {
     };
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Control {
+    paused: bool,
+    cancelled: bool,
+    interval: Option<Duration>,
+}
+
+///Handle to a Task spawned at runtime, dropping the handle detaches the task
+#[derive(Debug)]
+pub struct TaskHandle {
+    control: watch::Sender<Control>,
+    handle: JoinHandle<()>,
+}
+
+impl TaskHandle {
+    ///Stops the task before its next run, a run in progress is allowed to finish
+    pub fn cancel(&self) {self.control.send_modify(|c| c.cancelled = true);}
+    pub fn pause(&self) {self.control.send_modify(|c| c.paused = true);}
+    pub fn resume(&self) {self.control.send_modify(|c| c.paused = false);}
+    ///Overrides the interval reported by the task, restarting the wait from the last run
+    pub fn set_interval(&self, interval: Duration) {
+        self.control.send_modify(|c| c.interval = Some(interval));
+    }
+    pub fn is_finished(&self) -> bool {self.handle.is_finished()}
+    ///Waits for the task to be cancelled or to stop reporting an interval
+    pub async fn join(self) {let _ = self.handle.await;}
+}
+
+///Execution statistics of a Task, kept for a while after it finishes
+#[derive(Debug, Clone, Default)]
+pub struct TaskMetrics {
+    pub name: String,
+    pub runs: u64,
+    pub failures: u64,
+    pub running: bool,
+    ///The task was cancelled, stopped with the Runtime or has no runs left
+    pub finished: bool,
+    ///The task was stopped by RetryPolicy::Disable after failing
+    pub disabled: bool,
+    pub last_duration: Option<Duration>,
+    pub max_duration: Duration,
+    pub last_error: Option<String>,
+    ///Intervals skipped because the task started late or ran longer than its interval
+    pub missed: u64,
+}
+
+impl fmt::Display for TaskMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} runs, {} failed, last {:?}, max {:?}, {} missed",
+            self.name, self.runs, self.failures, self.last_duration.unwrap_or_default(), self.max_duration, self.missed
+        )?;
+        if self.running {write!(f, ", running")?;}
+        if self.disabled {write!(f, ", disabled")?;} else if self.finished {write!(f, ", finished")?;}
+        match &self.last_error {
+            Some(error) => write!(f, ", last error: {}", error),
+            None => Ok(())
+        }
+    }
+}
+
+type Metrics = Arc<std::sync::Mutex<BTreeMap<u64, TaskMetrics>>>;
+
+///Owns a task's entry in the metrics, marking it finished once the task is done
+struct Recorder(Metrics, u64);
+
+impl Recorder {
+    fn new(metrics: Metrics, name: String) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        metrics.lock().unwrap().insert(id, TaskMetrics{name, ..Default::default()});
+        Recorder(metrics, id)
+    }
+
+    fn record(&self, update: impl FnOnce(&mut TaskMetrics)) {
+        if let Some(metrics) = self.0.lock().unwrap().get_mut(&self.1) {update(metrics);}
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let mut metrics = self.0.lock().unwrap();
+        if let Some(metrics) = metrics.get_mut(&self.1) {
+            metrics.running = false;
+            metrics.finished = true;
+        }
+        let finished = metrics.iter().filter(|(_, metrics)| metrics.finished).map(|(id, _)| *id).collect::<Vec<_>>();
+        for id in finished.iter().take(finished.len().saturating_sub(FINISHED)) {metrics.remove(id);}
+    }
+}
+
+///Spawns Tasks onto the Runtime, paused along with the app unless created for background tasks
+#[derive(Debug, Clone)]
+pub struct Spawner {
+    handle: Handle,
+    paused: Option<watch::Receiver<bool>>,
+    shutdown: Option<watch::Receiver<bool>>,
+    live: Arc<watch::Sender<usize>>,
+    metrics: Metrics,
+    clock: Arc<dyn Clock>,
+}
+
+impl Spawner {
+    ///Spawner for the tokio runtime the caller is running on, never paused
+    pub fn current() -> Self {
+        Self::with_clock(SystemClock)
+    }
+
+    ///Spawner for the tokio runtime the caller is running on, timing tasks with the given clock
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Spawner{
+            handle: Handle::current(),
+            paused: None,
+            shutdown: None,
+            live: Arc::new(watch::channel(0).0),
+            metrics: Metrics::default(),
+            clock: Arc::new(clock)
+        }
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {self.clock.clone()}
+
+    ///Metrics of the tasks spawned through this Spawner's runtime, oldest first, including the
+    ///last few that finished
+    pub fn metrics(&self) -> Vec<TaskMetrics> {self.metrics.lock().unwrap().values().cloned().collect()}
+
+    pub(crate) fn handle(&self) -> &Handle {&self.handle}
+
+    pub fn spawn(&self, ctx: HeadlessContext, task: Box<dyn Task>) -> TaskHandle {
+        let (control, receiver) = watch::channel(Control::default());
+        let recorder = Recorder::new(self.metrics.clone(), task.name());
+        let manager = TaskManager{
+            ctx, task, recorder,
+            clock: self.clock.clone(),
+            paused: self.paused.clone(),
+            control: Some(receiver),
+            shutdown: self.shutdown.clone(),
+            was_paused: false,
+            _live: Live::new(self.live.clone()),
+        };
+        TaskHandle{control, handle: self.handle.spawn(manager.run())}
+    }
+}
+
+///Counts a running TaskManager so Runtime::close can wait for it
+struct Live(Arc<watch::Sender<usize>>);
+
+impl Live {
+    fn new(live: Arc<watch::Sender<usize>>) -> Self {
+        live.send_modify(|count| *count += 1);
+        Live(live)
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {self.0.send_modify(|count| *count -= 1);}
+}
+
+///Drives a single Task, sleeping on its Clock until the next scheduled run
 pub struct TaskManager {
     ctx: HeadlessContext,
-    tasks: Vec<(Box<dyn Task>, Instant)> 
+    task: Box<dyn Task>,
+    clock: Arc<dyn Clock>,
+    paused: Option<watch::Receiver<bool>>,
+    control: Option<watch::Receiver<Control>>,
+    shutdown: Option<watch::Receiver<bool>>,
+    was_paused: bool,
+    recorder: Recorder,
+    _live: Live,
 }
 
 impl TaskManager {
-    pub fn new(ctx: HeadlessContext, tasks: Tasks) -> Self {
-        TaskManager{ctx, tasks: tasks.into_iter().map(|t| (t, Instant::now())).collect()}
+    ///Runs the task on its schedule until it is stopped, disabled or has no runs left
+    pub async fn run(mut self) {
+        let start = self.clock.now();
+        let mut last = None;
+        let last_run = match self.task.schedule() {
+            Schedule::Cron(cron) => self.last_run(&cron).await,
+            _ => None
+        };
+        let mut last_wall = last_run.unwrap_or_else(|| self.clock.system_time());
+        let mut failures = 0;
+        self.was_paused = self.paused();
+        loop {
+            if self.stopped() {return self.task.on_shutdown(&mut self.ctx).await;}
+            let schedule = self.control().interval.map(Schedule::Every).unwrap_or_else(|| self.task.schedule());
+            let retry = last.zip(self.task.retry().delay(failures)).map(|(last, delay)| Due::At(last + delay));
+            let Some(due) = retry.or_else(|| self.due(&schedule, start, last, last_wall)) else {return;};
+            let wake = tokio::select! {
+                _ = Self::sleep(due, self.clock.clone()) => Wake::Due,
+                _ = Self::changed(&mut self.paused) => Wake::Lifecycle,
+                _ = Self::changed(&mut self.control) => Wake::Control,
+                _ = Self::changed(&mut self.shutdown) => Wake::Control,
+            };
+            if let (Wake::Due, Due::At(due), Schedule::Every(interval)) = (wake, due, &schedule) {
+                let late = self.clock.now().saturating_duration_since(due);
+                let missed = late.as_nanos() / interval.as_nanos().max(1);
+                if missed > 0 {self.recorder.record(|metrics| metrics.missed += missed as u64);}
+            }
+            self.pause_hooks().await;
+            let resumed = wake == Wake::Lifecycle && matches!(due, Due::Resume) && !self.paused();
+            if wake != Wake::Due && !resumed {continue;}
+            if !self.resumed().await {continue;}
+            last = Some(self.clock.now());
+            if let Schedule::Cron(cron) = &schedule {
+                last_wall = self.clock.system_time();
+                self.set_last_run(cron, last_wall).await;
+            }
+            self.recorder.record(|metrics| metrics.running = true);
+            let result = self.task.run(&mut self.ctx).await;
+            let duration = self.clock.now().saturating_duration_since(last.unwrap());
+            self.recorder.record(|metrics| {
+                metrics.running = false;
+                metrics.runs += 1;
+                metrics.last_duration = Some(duration);
+                metrics.max_duration = metrics.max_duration.max(duration);
+                if let Err(error) = &result {
+                    metrics.failures += 1;
+                    metrics.last_error = Some(error.to_string());
+                }
+            });
+            match result {
+                Ok(()) => failures = 0,
+                Err(error) => {
+                    failures += 1;
+                    let policy = self.task.retry();
+                    let disabled = policy == RetryPolicy::Disable;
+                    log::error!("Task {} failed (attempt {}): {}", self.task.name(), failures, error);
+                    self.ctx.post(TaskFailed{task: self.task.name(), error: error.to_string(), attempt: failures, disabled});
+                    if disabled {
+                        self.recorder.record(|metrics| metrics.disabled = true);
+                        return;
+                    }
+                    if policy.delay(failures).is_none() {failures = 0;}
+                }
+            }
+        }
     }
 
-    pub async fn tick(&mut self) {
-        for (task, time) in self.tasks.iter_mut() {
-            if let Some(duration) = task.interval() {
-                if time.elapsed() > duration {
-                    *time = Instant::now();
-                    task.run(&mut self.ctx).await;
-                }
+    fn due(&self, schedule: &Schedule, start: Instant, last: Option<Instant>, last_wall: SystemTime) -> Option<Due> {
+        match schedule {
+            Schedule::Every(interval) => Some(Due::At(last.unwrap_or(start) + *interval)),
+            Schedule::Once => last.is_none().then_some(Due::At(start)),
+            Schedule::After(delay) => last.is_none().then_some(Due::At(start + *delay)),
+            Schedule::Cron(cron) => cron.next_after(last_wall).map(|next| Due::At(
+                self.clock.now() + next.duration_since(self.clock.system_time()).unwrap_or_default()
+            )),
+            Schedule::OnResume => Some(Due::Resume),
+            Schedule::Never => None
+        }
+    }
+
+    async fn sleep(due: Due, clock: Arc<dyn Clock>) {
+        match due {
+            Due::At(instant) => clock.sleep_until(instant).await,
+            Due::Resume => std::future::pending().await
+        }
+    }
+
+    ///Key of the last run time, so instances of a task type with different expressions keep their own
+    fn last_run_key(&self, cron: &Cron) -> String {format!("{} {}", self.task.name(), cron)}
+
+    async fn last_run(&mut self, cron: &Cron) -> Option<SystemTime> {
+        let secs = match self.ctx.cache.namespace(LAST_RUNS).get::<u64>(&self.last_run_key(cron)).await {
+            Ok(secs) => secs?,
+            Err(error) => {
+                log::warn!("Could not read last run of {}: {}", self.task.name(), error);
+                return None;
             }
+        };
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    async fn set_last_run(&mut self, cron: &Cron, time: SystemTime) {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if let Err(error) = self.ctx.cache.namespace(LAST_RUNS).set(&self.last_run_key(cron), &secs).await {
+            log::warn!("Could not record last run of {}: {}", self.task.name(), error);
+        }
+    }
+
+    fn control(&self) -> Control {
+        self.control.as_ref().map(|c| *c.borrow()).unwrap_or_default()
+    }
+
+    fn paused(&self) -> bool {
+        self.paused.as_ref().is_some_and(|p| *p.borrow()) || self.control().paused
+    }
+
+    fn stopped(&self) -> bool {
+        self.control().cancelled || self.shutdown.as_ref().is_some_and(|s| *s.borrow())
+    }
+
+    ///Tells the task when it has been paused or resumed since the last check
+    async fn pause_hooks(&mut self) {
+        let paused = self.paused();
+        if paused == self.was_paused {return;}
+        self.was_paused = paused;
+        match paused {
+            true => self.task.on_pause(&mut self.ctx).await,
+            false => self.task.on_resume(&mut self.ctx).await
+        }
+    }
+
+    ///Waits until neither the Runtime nor the TaskHandle has the task paused
+    async fn resumed(&mut self) -> bool {
+        while self.paused() && !self.stopped() {
+            tokio::select! {
+                _ = Self::changed(&mut self.paused) => {},
+                _ = Self::changed(&mut self.control) => {},
+                _ = Self::changed(&mut self.shutdown) => {}
+            }
+            self.pause_hooks().await;
+        }
+        !self.stopped()
+    }
+
+    ///Resolves on the next change, or never once the sender is dropped, the receiver still
+    ///holding the last value sent so a dropped TaskHandle leaves the task as it was
+    async fn changed<T>(receiver: &mut Option<watch::Receiver<T>>) {
+        match receiver.as_mut() {
+            Some(receiver) => if receiver.changed().await.is_err() {std::future::pending().await},
+            None => std::future::pending().await
         }
     }
 }
@@ -48,70 +453,76 @@ impl TaskManager {
 ///Runs Active Tasks (and Background Tasks on mobile/wasm)
 pub struct Runtime {
     runtime: Option<tokio::runtime::Runtime>,
-    active_thread: Sender<u8>,
+    paused: watch::Sender<bool>,
+    shutdown: watch::Sender<bool>,
+    live: Arc<watch::Sender<usize>>,
+    metrics: Metrics,
+    clock: Arc<dyn Clock>,
 }
 
 impl Runtime {
     pub async fn new_background(ctx: HeadlessContext, tasks: Tasks) {
-        Self::background_thread(TaskManager::new(ctx, tasks)).await
+        for task in tasks {ctx.spawner.spawn(ctx.clone(), task);}
+        std::future::pending::<()>().await
+    }
+
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
     }
 
-    pub async fn new(
-        ctx: HeadlessContext, background_tasks: Tasks, tasks: Tasks
-    ) -> Self {
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
         let threads = if cfg!(any(target_os = "ios", target_os = "android")) {2} else {1};
         let runtime = tokio::runtime::Builder::new_multi_thread().enable_time().enable_io().worker_threads(threads).build().unwrap();
-
-        if !background_tasks.is_empty() {
-            let task_manager = TaskManager::new(ctx.clone(), background_tasks);
-            runtime.spawn(Self::background_thread(task_manager));
+        Runtime{
+            runtime: Some(runtime),
+            paused: watch::channel(false).0,
+            shutdown: watch::channel(false).0,
+            live: Arc::new(watch::channel(0).0),
+            metrics: Metrics::default(),
+            clock: Arc::new(clock)
         }
-
-        let (active_thread, receiver) = channel();
-        runtime.spawn(Self::thread(TaskManager::new(ctx, tasks), receiver));
-
-        Runtime{runtime: Some(runtime), active_thread}
     }
 
-    async fn thread(mut task_manager: TaskManager, receiver: Receiver<u8>) {
-        let mut paused = false;
-        loop {
-            if !paused {
-                match receiver.try_recv() {
-                    Ok(0) => {},
-                    Ok(1) => {paused = true}
-                    Err(TryRecvError::Empty) => {
-                        task_manager.tick().await;
-                    },
-                    _ => {return;}
-                }
-            } else {
-                match receiver.recv() {
-                    Ok(0) => {paused = false;},
-                    Ok(1) => {},
-                    _ => {return;}
-                }
-            }
-            std::thread::sleep(Duration::from_millis(THREAD_TICK));
+    ///Spawner for active tasks, which are paused with the app
+    pub fn spawner(&self) -> Spawner {
+        Spawner{
+            handle: self.runtime.as_ref().unwrap().handle().clone(),
+            paused: Some(self.paused.subscribe()),
+            shutdown: Some(self.shutdown.subscribe()),
+            live: self.live.clone(),
+            metrics: self.metrics.clone(),
+            clock: self.clock.clone()
         }
     }
 
-    async fn background_thread(mut task_manager: TaskManager) {
-        loop {
-            task_manager.tick().await;
-            std::thread::sleep(Duration::from_millis(THREAD_TICK));
-        }
+    pub fn start(&self, ctx: &HeadlessContext, background_tasks: Tasks, tasks: Tasks) {
+        let background = Spawner{paused: None, ..self.spawner()};
+        for task in background_tasks {background.spawn(ctx.clone(), task);}
+        let spawner = self.spawner();
+        for task in tasks {spawner.spawn(ctx.clone(), task);}
     }
 
     pub fn pause(&mut self) {
-        self.active_thread.send(1).unwrap();
+        self.paused.send_replace(true);
     }
 
     pub fn resume(&mut self) {
-        self.active_thread.send(0).unwrap();
+        self.paused.send_replace(false);
     }
 
-    pub fn close(&mut self) {
+    ///Asks every task to stop and waits up to the grace period for them before stopping the runtime
+    pub async fn close(&mut self) {
+        self.shutdown.send_replace(true);
+        let mut live = self.live.subscribe();
+        if tokio::time::timeout(SHUTDOWN_GRACE, live.wait_for(|count| *count == 0)).await.is_err() {
+            log::warn!("Tasks still running after {:?}, stopping them", SHUTDOWN_GRACE);
+        }
         self.runtime.take().unwrap().shutdown_background();
     }
 }
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SEARCH_DAYS: u64 = 366 * 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCron(pub String);
+
+impl std::fmt::Display for InvalidCron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid cron expression: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCron {}
+
+///Wall-clock schedule in UTC from a five field cron expression (minute hour day month weekday)
+///
+///Fields accept `*`, single values, ranges `a-b`, steps `*/n` or `a-b/n` and lists `a,b`.
+///Weekdays run from 0 (Sunday) to 6, with 7 also accepted as Sunday.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    ///The fields of the expression, separated by single spaces
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self, InvalidCron> {
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {return Err(InvalidCron(expr.to_string()));}
+        let field = |i: usize, min: u32, max: u32| Self::field(fields[i], min, max).ok_or_else(|| InvalidCron(expr.to_string()));
+        let weekdays = field(4, 0, 7)?;
+        Ok(Cron{
+            expr: fields.join(" "),
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            weekdays: (weekdays | (weekdays >> 7)) & 0x7f,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn field(field: &str, min: u32, max: u32) -> Option<u64> {
+        field.split(',').try_fold(0u64, |bits, part| {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+                None => (part, 1)
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                    None => {
+                        let value = range.parse().ok()?;
+                        (value, if part.contains('/') {max} else {value})
+                    }
+                }
+            };
+            if start < min || end > max || start > end {return None;}
+            Some((start..=end).step_by(step as usize).fold(bits, |bits, v| bits | (1 << v)))
+        })
+    }
+
+    fn matches_day(&self, day: u32, weekday: u32) -> bool {
+        let day = self.days & (1 << day) != 0;
+        let weekday = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday
+        }
+    }
+
+    ///First matching minute strictly after the given time
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let after = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 60 + 1;
+        let first_day = after / (24 * 60);
+        (first_day..first_day + SEARCH_DAYS).find_map(|day| {
+            let (_, month, date) = civil_from_days(day);
+            let weekday = ((day + 4) % 7) as u32;
+            if self.months & (1 << month) == 0 || !self.matches_day(date, weekday) {return None;}
+            (0..24u64).filter(|h| self.hours & (1 << h) != 0).find_map(|hour|
+                (0..60u64).filter(|m| self.minutes & (1 << m) != 0)
+                    .map(|minute| (day * 24 + hour) * 60 + minute)
+                    .find(|minute| *minute >= after)
+            )
+        }).map(|minute| UNIX_EPOCH + Duration::from_secs(minute * 60))
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {write!(f, "{}", self.expr)}
+}
+
+impl std::str::FromStr for Cron {
+    type Err = InvalidCron;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {Cron::parse(s)}
+}
+
+///Converts days since the unix epoch into a (year, month, day) civil date
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 {mp + 3} else {mp - 9}) as u32;
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {UNIX_EPOCH + Duration::from_secs(secs)}
+
+    fn next(expr: &str, after: u64) -> u64 {
+        let next = Cron::parse(expr).unwrap().next_after(at(after)).unwrap();
+        next.duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+
+    #[test]
+    fn day_or_weekday() {
+        //Monday 2024-01-01, the 13th or any Friday matches the first Friday
+        assert_eq!(next("0 0 13 * 5", 1_704_067_200), 1_704_412_800);
+        //Only the day restricted, the weekday is ignored
+        assert_eq!(next("0 0 13 * *", 1_704_067_200), 1_705_104_000);
+    }
+
+    #[test]
+    fn sunday_as_seven() {
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, Cron::parse("0 0 * * 0").unwrap().weekdays);
+        assert_eq!(next("0 0 * * 7", 1_704_067_200), 1_704_585_600);
+        assert!(Cron::parse("0 0 * * 8").is_err());
+    }
+
+    #[test]
+    fn leap_days() {
+        //From 2024-03-01 to 2028-02-29, and skipping 2100 which is not a leap year
+        assert_eq!(next("0 0 29 2 *", 1_709_251_200), 1_835_395_200);
+        assert_eq!(next("0 0 29 2 *", 3_981_398_400), 4_233_686_400);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Cron::parse(" 0  3 * *   1-5 ").unwrap().to_string(), "0 3 * * 1-5");
+    }
+}
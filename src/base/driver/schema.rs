@@ -0,0 +1,225 @@
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::state::Field;
+
+pub type Migration = Arc<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+///First byte of a stored payload that is not plain JSON, which never starts with a byte this high,
+///with the flags below in its low bits
+pub(crate) const HEADER: u8 = 0x80;
+const CBOR: u8 = 0x01;
+const DEFLATE: u8 = 0x02;
+///Set alone by the Cache on the values it encrypts, around a payload with its own header
+pub(crate) const ENCRYPTED: u8 = 0x04;
+
+static SCHEMAS: LazyLock<RwLock<HashMap<TypeId, Schema>>> = LazyLock::new(RwLock::default);
+
+///Two registered Field types would be stored under the same ident
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateIdent {
+    pub ident: String,
+    pub registered: &'static str,
+    pub duplicate: &'static str,
+}
+
+impl std::fmt::Display for DuplicateIdent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} and {} both use the Field ident {}", self.registered, self.duplicate, self.ident)
+    }
+}
+
+impl std::error::Error for DuplicateIdent {}
+
+///Format a Field is encoded in when stored in the Cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    ///Compact binary encoding, smaller and faster to decode than JSON
+    Cbor,
+}
+
+///How a Field type is stored: the ident it is stored under, its encoding in the Cache, its version
+///and the migrations that bring older stored payloads up to that version
+///
+///Register it at startup, before the type is first read from the State or Cache:
+///```ignore
+///Schema::new(2)
+///    .ident("settings")
+///    .encoding(Encoding::Cbor)
+///    .compressed()
+///    .migration(0, |mut v| {v["theme"] = "light".into(); Ok(v)})
+///    .migration(1, |mut v| {v["volume"] = v["volume"].as_f64().map(|v| v / 100.0).into(); Ok(v)})
+///    .register::<Settings>()?;
+///```
+#[derive(Clone, Default)]
+pub struct Schema {
+    version: u32,
+    ident: Option<String>,
+    type_name: &'static str,
+    encoding: Encoding,
+    compressed: bool,
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl std::fmt::Debug for Schema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Schema")
+            .field("version", &self.version)
+            .field("ident", &self.ident)
+            .field("type_name", &self.type_name)
+            .field("encoding", &self.encoding)
+            .field("compressed", &self.compressed)
+            .field("migrations", &self.migrations.keys())
+            .finish()
+    }
+}
+
+impl Schema {
+    pub fn new(version: u32) -> Self {
+        Schema{version, ..Default::default()}
+    }
+
+    ///Pins the ident the type is stored under, which otherwise is its type name and changes
+    ///whenever the type is moved or the crate renamed
+    pub fn ident(mut self, ident: &str) -> Self {
+        self.ident = Some(ident.to_string());
+        self
+    }
+
+    ///Encoding of the type in the Cache, values stored in another encoding are still read
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    ///Deflates the encoded value before storing it, worth it for large values
+    pub fn compressed(mut self) -> Self {
+        self.compressed = true;
+        self
+    }
+
+    ///Upgrades a payload stored at version `from` to version `from + 1`
+    pub fn migration(
+        mut self, from: u32, migrate: impl Fn(Value) -> Result<Value, String> + Send + Sync + 'static
+    ) -> Self {
+        self.migrations.insert(from, Arc::new(migrate));
+        self
+    }
+
+    ///Makes this the schema of F, replacing any registered before,
+    ///unless another registered type is already stored under the same ident
+    pub fn register<F: Field + 'static>(mut self) -> Result<(), DuplicateIdent> {
+        self.type_name = std::any::type_name::<F>();
+        let ident = self.ident.as_deref().unwrap_or(self.type_name);
+        let mut schemas = SCHEMAS.write().unwrap();
+        let duplicate = schemas.iter().find(|(id, schema)| {
+            **id != TypeId::of::<F>() && schema.ident.as_deref().unwrap_or(schema.type_name) == ident
+        });
+        if let Some((_, schema)) = duplicate {
+            return Err(DuplicateIdent{ident: ident.to_string(), registered: schema.type_name, duplicate: self.type_name});
+        }
+        if let Some(from) = (0..self.version).find(|from| !self.migrations.contains_key(from)) {
+            log::warn!("{} has no migration from version {}, older payloads will be decoded as they are", ident, from);
+        }
+        schemas.insert(TypeId::of::<F>(), self);
+        Ok(())
+    }
+
+    ///Ident pinned for F, if one was registered
+    pub fn pinned<F: 'static>() -> Option<String> {
+        SCHEMAS.read().unwrap().get(&TypeId::of::<F>()).and_then(|schema| schema.ident.clone())
+    }
+
+    ///Current version of F, 0 until a schema is registered
+    pub fn version<F: 'static>() -> u32 {
+        SCHEMAS.read().unwrap().get(&TypeId::of::<F>()).map(|schema| schema.version).unwrap_or_default()
+    }
+
+    fn get<F: 'static>() -> Schema {
+        SCHEMAS.read().unwrap().get(&TypeId::of::<F>()).cloned().unwrap_or_default()
+    }
+
+    ///Flags of the header byte values of this schema are stored with, 0 for plain JSON
+    fn flags(&self) -> u8 {
+        (if self.encoding == Encoding::Cbor {CBOR} else {0}) | (if self.compressed {DEFLATE} else {0})
+    }
+
+    ///Encodes the value for the Cache as its Schema asks, headed by a flags byte unless it is plain JSON
+    pub(crate) fn encode<F: Field + 'static>(item: &F) -> Vec<u8> {
+        let flags = Self::get::<F>().flags();
+        let mut bytes = if flags == 0 {Vec::new()} else {vec![HEADER | flags]};
+        if flags & DEFLATE != 0 {
+            let mut encoder = DeflateEncoder::new(bytes, Compression::default());
+            Self::serialize(item, flags, &mut encoder);
+            return encoder.finish().unwrap();
+        }
+        Self::serialize(item, flags, &mut bytes);
+        bytes
+    }
+
+    fn serialize<F: Field>(item: &F, flags: u8, writer: impl Write) {
+        match flags & CBOR != 0 {
+            true => ciborium::into_writer(item, writer).unwrap(),
+            false => serde_json::to_writer(writer, item).unwrap()
+        }
+    }
+
+    fn deserialize<T: for<'a> Deserialize<'a>>(flags: u8, payload: &[u8]) -> Result<T, String> {
+        match flags & CBOR != 0 {
+            true => ciborium::from_reader(payload).map_err(|error| error.to_string()),
+            false => serde_json::from_slice(payload).map_err(|error| error.to_string())
+        }
+    }
+
+    ///Splits off the flags, 0 for plain JSON, inflating the payload if it was compressed
+    fn payload(bytes: &[u8]) -> Result<(u8, Cow<'_, [u8]>), String> {
+        let Some((&header, payload)) = bytes.split_first().filter(|(header, _)| **header & HEADER != 0) else {
+            return Ok((0, Cow::Borrowed(bytes)));
+        };
+        let flags = header & !HEADER;
+        if flags & DEFLATE == 0 {return Ok((flags, Cow::Borrowed(payload)));}
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(payload).read_to_end(&mut inflated).map_err(|error| error.to_string())?;
+        Ok((flags, Cow::Owned(inflated)))
+    }
+
+    ///Decodes a stored payload of any type as far as JSON can represent it
+    pub(crate) fn inspect(bytes: &[u8]) -> Option<Value> {
+        let (flags, payload) = Self::payload(bytes).ok()?;
+        Self::deserialize(flags, &payload).ok()
+    }
+
+    ///Decodes a payload stored at the given version, migrating it to the current version first,
+    ///and returns it re-encoded when it was migrated or stored in another encoding
+    pub(crate) fn decode<F: Field + 'static>(version: u32, bytes: &[u8]) -> Result<(F, Option<Vec<u8>>), String> {
+        let schema = Self::get::<F>();
+        let (flags, payload) = Self::payload(bytes)?;
+        let item: F = match version < schema.version {
+            true => {
+                let mut value: Value = Self::deserialize(flags, &payload)?;
+                for from in version..schema.version {
+                    if let Some(migrate) = schema.migrations.get(&from) {
+                        value = migrate(value).map_err(|error| format!("migration from version {} failed: {}", from, error))?;
+                    }
+                }
+                serde_json::from_value(value).map_err(|error| error.to_string())?
+            },
+            false => Self::deserialize(flags, &payload)?
+        };
+        let stale = version < schema.version || flags != schema.flags();
+        let bytes = stale.then(|| Self::encode(&item));
+        Ok((item, bytes))
+    }
+}
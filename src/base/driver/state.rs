@@ -1,28 +1,260 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
+use std::marker::PhantomData;
 
 use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use super::schema::Schema;
+
+mod history;
+use history::{Edit, History};
 
 pub trait Field: Serialize + for<'a> Deserialize <'a> + Default + Debug {
+    ///Key the type is stored under, the ident pinned by its Schema or else its type name
     fn ident() -> String where Self: Sized + 'static {
-        std::any::type_name::<Self>().to_string()
+        Schema::pinned::<Self>().unwrap_or_else(|| std::any::type_name::<Self>().to_string())
     }
     fn to_bytes(&self) -> Vec<u8> {serde_json::to_vec(self).unwrap()}
     fn from_bytes(bytes: &[u8]) -> Self where Self: Sized {
-        serde_json::from_slice(bytes).unwrap_or_default()
+        Self::try_from_bytes(bytes).unwrap_or_default()
+    }
+    fn try_from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> where Self: Sized {
+        serde_json::from_slice(bytes)
     }
 }
 
 impl<I: Serialize + for<'a> Deserialize <'a> + Default + Debug> Field for I {}
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct State(HashMap<String, Vec<u8>>);
+///A persisted field as a JSON value, with the Schema version of its type when it was saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persisted {
+    pub version: u32,
+    pub value: serde_json::Value,
+}
+
+///Persisted fields of a State keyed by ident, stored in the Cache between runs
+pub type Snapshot = BTreeMap<String, Persisted>;
+
+///Sets buffered for each watcher before the oldest are dropped
+const CHANGES: usize = 256;
+
+///A value written to the State as its ident, bytes, Schema version when restored at an older one,
+///and the revision once written
+type Change = (String, Vec<u8>, Option<u32>, u64);
+
+fn changes() -> broadcast::Sender<Change> {broadcast::channel(CHANGES).0}
+
+///Decodes a value of type F, migrating it first when it was stored at an older Schema version,
+///and logs why it could not be decoded before falling back to the default
+fn decode<F: Field + 'static>(bytes: &[u8], version: Option<u32>) -> F {
+    let decoded = match version {
+        Some(version) => Schema::decode::<F>(version, bytes).map(|(item, _)| item),
+        None => F::try_from_bytes(bytes).map_err(|error| error.to_string())
+    };
+    decoded.unwrap_or_else(|error| {
+        log::warn!("Could not decode state field {}: {}", F::ident(), error);
+        F::default()
+    })
+}
+
+///A value of type F was set on the State
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChanged<F> {
+    pub value: F,
+    ///Revision of the State once it was set
+    pub revision: u64,
+}
+
+///Values of type F set on the State, as they are set
+#[derive(Debug)]
+pub struct StateWatch<F> {
+    changes: broadcast::Receiver<Change>,
+    ident: String,
+    _p: PhantomData<fn() -> F>,
+}
+
+impl<F: Field + 'static> StateWatch<F> {
+    fn matches(&self, (ident, bytes, version, revision): Change) -> Option<StateChanged<F>> {
+        (ident == self.ident).then(|| StateChanged{value: decode(&bytes, version), revision})
+    }
+
+    ///Waits for the next value to be set
+    pub async fn next(&mut self) -> StateChanged<F> {
+        loop {
+            match self.changes.recv().await {
+                Ok(change) => if let Some(changed) = self.matches(change) {return changed;},
+                Err(broadcast::error::RecvError::Lagged(missed)) => log::warn!("State watch missed {} changes", missed),
+                Err(broadcast::error::RecvError::Closed) => std::future::pending().await
+            }
+        }
+    }
+
+    ///Takes the oldest value set that has not been seen yet
+    pub fn try_next(&mut self) -> Option<StateChanged<F>> {
+        loop {
+            match self.changes.try_recv() {
+                Ok(change) => if let Some(changed) = self.matches(change) {return Some(changed);},
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => log::warn!("State watch missed {} changes", missed),
+                Err(_) => return None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    values: HashMap<String, Vec<u8>>,
+    ///Idents kept across restarts, with the Schema version their values are stored at
+    #[serde(skip)]
+    persisted: HashMap<String, u32>,
+    ///Versions of the values restored from a snapshot that were not decoded as their type since
+    #[serde(skip)]
+    stale: HashMap<String, u32>,
+    #[serde(skip)]
+    revision: u64,
+    #[serde(skip, default = "changes")]
+    changes: broadcast::Sender<Change>,
+    #[serde(skip)]
+    history: History,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State{
+            values: HashMap::new(), persisted: HashMap::new(), stale: HashMap::new(), revision: 0,
+            changes: changes(), history: History::default()
+        }
+    }
+}
+
 impl State {
+    ///Sets the value of type F, notifying its watchers unless it is unchanged
     pub fn set<F: Field + 'static>(&mut self, item: &F) {
-        self.0.insert(F::ident(), item.to_bytes());
+        let ident = F::ident();
+        let bytes = item.to_bytes();
+        if self.values.get(&ident) == Some(&bytes) {return;}
+        if self.history.tracked.contains(&ident) {
+            let before = self.values.contains_key(&ident).then(|| self.get::<F>().to_bytes());
+            self.history.record(Edit{ident: ident.clone(), before, after: Some(bytes.clone())});
+        }
+        if let Some(version) = self.persisted.get_mut(&ident) {*version = Schema::version::<F>();}
+        self.write(ident, Some(bytes));
+    }
+
+    ///Sets or removes a value without recording it, notifying its watchers
+    fn write(&mut self, ident: String, bytes: Option<Vec<u8>>) {self.write_at(ident, bytes, None);}
+
+    ///Writes a value stored at an older Schema version when given one, decoded through its
+    ///migrations until it is set again
+    fn write_at(&mut self, ident: String, bytes: Option<Vec<u8>>, version: Option<u32>) {
+        self.revision += 1;
+        match version {
+            Some(version) => self.stale.insert(ident.clone(), version),
+            None => self.stale.remove(&ident)
+        };
+        let _ = self.changes.send((ident.clone(), bytes.clone().unwrap_or_default(), version, self.revision));
+        match bytes {
+            Some(bytes) => self.values.insert(ident, bytes),
+            None => self.values.remove(&ident)
+        };
     }
     pub fn get<F: Field + 'static>(&self) -> F {
-        self.0.get(&F::ident()).map(|b| F::from_bytes(b)).unwrap_or_default()
+        let ident = F::ident();
+        self.values.get(&ident).map(|bytes| decode(bytes, self.stale.get(&ident).copied())).unwrap_or_default()
+    }
+    ///Counts the changes to the State, so work depending on it can be skipped while it stays the same
+    pub fn revision(&self) -> u64 {self.revision}
+    ///Values of type F as they are set from now on
+    pub fn watch<F: Field + 'static>(&self) -> StateWatch<F> {
+        StateWatch{changes: self.changes.subscribe(), ident: F::ident(), _p: PhantomData}
+    }
+    ///Moves the value stored under an old ident to a new one, along with whether it is persisted
+    ///and tracked, returning whether there was one
+    pub fn rename(&mut self, from: &str, to: &str) -> bool {
+        let Some(bytes) = self.values.get(from).cloned() else {return false;};
+        let stale = self.stale.get(from).copied();
+        self.write(from.to_string(), None);
+        self.write_at(to.to_string(), Some(bytes), stale);
+        if let Some(version) = self.persisted.remove(from) {self.persisted.insert(to.to_string(), version);}
+        self.history.rename(from, to);
+        true
+    }
+    ///Keeps the value of type F across restarts, saved when the app pauses or closes
+    ///and restored before it starts again
+    pub fn persist<F: Field + 'static>(&mut self) {
+        let ident = F::ident();
+        if let Some(version) = self.stale.get(&ident).copied() {
+            match Schema::decode::<F>(version, &self.values[&ident]) {
+                Ok((item, _)) => {
+                    self.stale.remove(&ident);
+                    self.values.insert(ident.clone(), item.to_bytes());
+                },
+                Err(error) => log::warn!("Could not migrate state field {}: {}", ident, error)
+            }
+        }
+        if self.persisted.insert(ident, Schema::version::<F>()).is_none() {self.revision += 1;}
+    }
+    ///Stops keeping the value of type F across restarts
+    pub fn forget<F: Field + 'static>(&mut self) {
+        if self.persisted.remove(&F::ident()).is_some() {self.revision += 1;}
+    }
+
+    ///Records the previous values of F so setting it can be undone
+    pub fn track<F: Field + 'static>(&mut self) {self.history.tracked.insert(F::ident());}
+    ///Stops recording the values of F, keeping the steps already recorded
+    pub fn untrack<F: Field + 'static>(&mut self) {self.history.tracked.remove(&F::ident());}
+    ///Keeps at most this many undo steps, dropping the oldest
+    pub fn set_history_limit(&mut self, limit: usize) {self.history.set_limit(limit);}
+    ///Groups the changes made until the matching end_group into a single undo step
+    pub fn begin_group(&mut self) {self.history.begin();}
+    pub fn end_group(&mut self) {self.history.end();}
+    pub fn can_undo(&self) -> bool {self.history.can_undo()}
+    pub fn can_redo(&self) -> bool {self.history.can_redo()}
+    pub fn clear_history(&mut self) {self.history.clear();}
+
+    ///Restores the tracked values from before the last step, returning false if there was none
+    pub fn undo(&mut self) -> bool {
+        let Some(edits) = self.history.undo() else {return false;};
+        for edit in edits {self.write(edit.ident, edit.before);}
+        true
+    }
+
+    ///Sets the tracked values of the last undone step again, returning false if there was none
+    pub fn redo(&mut self) -> bool {
+        let Some(edits) = self.history.redo() else {return false;};
+        for edit in edits {self.write(edit.ident, edit.after);}
+        true
+    }
+
+    ///Every value decoded to JSON, keyed by ident
+    pub fn dump(&self) -> BTreeMap<String, serde_json::Value> {
+        self.values.iter().filter_map(|(ident, bytes)| Some((ident.clone(), serde_json::from_slice(bytes).ok()?))).collect()
+    }
+
+    ///Replaces every value with those of a dump, notifying the watchers of each
+    pub fn load(&mut self, dump: BTreeMap<String, serde_json::Value>) {
+        let removed = self.values.keys().filter(|ident| !dump.contains_key(*ident)).cloned().collect::<Vec<_>>();
+        for ident in removed {self.write(ident, None);}
+        for (ident, value) in dump {self.write(ident, Some(serde_json::to_vec(&value).unwrap()));}
+    }
+
+    ///The persisted fields that are set, each with the version it is stored at
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        self.persisted.iter().filter_map(|(ident, version)| {
+            let value = serde_json::from_slice(self.values.get(ident)?).ok()?;
+            let version = self.stale.get(ident).copied().unwrap_or(*version);
+            Some((ident.clone(), Persisted{version, value}))
+        }).collect()
+    }
+
+    ///Sets the fields of a snapshot, persisting them again until they are forgotten, and migrates
+    ///each saved at an older version once it is read as its type
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) {
+        for (ident, Persisted{version, value}) in snapshot {
+            self.write_at(ident.clone(), Some(serde_json::to_vec(&value).unwrap()), Some(version));
+            self.persisted.insert(ident, version);
+        }
     }
 }
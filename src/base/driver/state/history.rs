@@ -0,0 +1,101 @@
+use std::collections::{HashSet, VecDeque};
+
+///Undo steps kept until State::set_history_limit changes it
+const LIMIT: usize = 100;
+
+///A tracked field set from one value to another, None where it was not set
+#[derive(Debug, Clone)]
+pub(super) struct Edit {
+    pub ident: String,
+    pub before: Option<Vec<u8>>,
+    pub after: Option<Vec<u8>>,
+}
+
+///Previous values of the tracked fields, each undo step being a group of edits
+#[derive(Debug)]
+pub(super) struct History {
+    pub tracked: HashSet<String>,
+    undo: VecDeque<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    group: Vec<Edit>,
+    depth: usize,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History{
+            tracked: HashSet::new(), undo: VecDeque::new(), redo: Vec::new(), group: Vec::new(), depth: 0, limit: LIMIT
+        }
+    }
+}
+
+impl History {
+    ///Records an edit as its own step, or as part of the open group where an earlier edit of
+    ///the same field keeps the value from before the group
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        match self.group.iter_mut().find(|grouped| grouped.ident == edit.ident) {
+            Some(grouped) => grouped.after = edit.after,
+            None => self.group.push(edit)
+        }
+        if self.depth == 0 {self.push();}
+    }
+
+    fn push(&mut self) {
+        let step = std::mem::take(&mut self.group);
+        if step.is_empty() {return;}
+        self.undo.push_back(step);
+        while self.undo.len() > self.limit {self.undo.pop_front();}
+    }
+
+    pub fn begin(&mut self) {self.depth += 1;}
+
+    pub fn end(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {self.push();}
+    }
+
+    ///Closes any open group so it can be undone
+    fn close(&mut self) {
+        self.depth = 0;
+        self.push();
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.undo.len() > self.limit {self.undo.pop_front();}
+    }
+
+    pub fn can_undo(&self) -> bool {!self.undo.is_empty() || !self.group.is_empty()}
+    pub fn can_redo(&self) -> bool {!self.redo.is_empty()}
+
+    ///The edits of the last step, in the order they are to be reverted
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        self.close();
+        let step = self.undo.pop_back()?;
+        self.redo.push(step.clone());
+        Some(step.into_iter().rev().collect())
+    }
+
+    ///The edits of the last undone step, in the order they are to be applied again
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.close();
+        let step = self.redo.pop()?;
+        self.undo.push_back(step.clone());
+        Some(step)
+    }
+
+    ///Moves the tracking and the recorded edits of a field to the ident it was renamed to
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if self.tracked.remove(from) {self.tracked.insert(to.to_string());}
+        let steps = self.undo.iter_mut().chain(self.redo.iter_mut()).chain(std::iter::once(&mut self.group));
+        for edit in steps.flatten().filter(|edit| edit.ident == from) {edit.ident = to.to_string();}
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group.clear();
+    }
+}
@@ -1,5 +1,6 @@
-use crate::base::window::{WindowAppTrait, WindowHandle, WindowEvent};
+use crate::base::window::{WindowAppTrait, WindowHandle, WindowEvent, WindowId, WindowRequest};
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::path::PathBuf;
 
@@ -8,6 +9,14 @@ pub trait RenderAppTrait<R: Renderer + ?Sized> {
     fn on_event(&mut self, event: R::Event) -> impl Future<Output = ()>;
     fn ctx(&mut self) -> &mut R::Context;
     fn close(self) -> impl Future<Output = R::Context>;
+
+    fn open_window(&mut self, id: WindowId, ctx: R::Context, width: f32, height: f32) -> impl Future<Output = ()>;
+    ///Handles an event of any window, MAIN included
+    fn on_window_event(&mut self, id: WindowId, event: R::Event) -> impl Future<Output = ()>;
+    ///Context of any window, MAIN included, None if the app did not open it
+    fn window_ctx(&mut self, id: WindowId) -> Option<&mut R::Context>;
+    fn close_window(&mut self, id: WindowId) -> impl Future<Output = Option<R::Context>>;
+    fn window_requests(&mut self) -> Vec<WindowRequest>;
 }
 
 pub trait HasLifeEvents {
@@ -24,24 +33,45 @@ pub trait Renderer {
     ) -> impl Future<Output = (Self, Self::Context, (f32, f32))> where Self: Sized;
         
     fn on_event<W: WindowHandle, A: RenderAppTrait<Self>>(
-        &mut self, app: &mut A, event: WindowEvent<W>
+        &mut self, app: &mut A, id: WindowId, event: WindowEvent<W>
     ) -> impl Future<Output = ()>;
     fn close(self, ctx: Self::Context) -> impl Future<Output = ()>;
 }
 
-pub struct RenderApp<R: Renderer, A: RenderAppTrait<R>>(R, A);
+///Renders the app to its main window and a Renderer per window it opened
+pub struct RenderApp<R: Renderer, A: RenderAppTrait<R>>(R, A, HashMap<WindowId, R>);
 impl<A: RenderAppTrait<R>, R: Renderer> WindowAppTrait for RenderApp<R, A> {
     async fn new<W: WindowHandle>(
         storage_path: PathBuf, window: W, width: u32, height: u32, scale_factor: f64
     ) -> Self where Self: Sized {
         let (renderer, ctx, size) = R::new(window, width, height, scale_factor).await;
         let app = A::new(storage_path, ctx, size.0, size.1).await;
-        RenderApp(renderer, app)
+        RenderApp(renderer, app, HashMap::new())
     }
     async fn on_event<W: WindowHandle>(&mut self, event: WindowEvent<W>) {
-        self.0.on_event(&mut self.1, event).await;
+        self.0.on_event(&mut self.1, WindowId::MAIN, event).await;
+    }
+    async fn close(mut self) {
+        for id in self.2.keys().copied().collect::<Vec<_>>() {self.close_window(id).await;}
+        let ctx = self.1.close().await;
+        self.0.close(ctx).await;
+    }
+
+    async fn open_window<W: WindowHandle>(
+        &mut self, id: WindowId, window: W, width: u32, height: u32, scale_factor: f64
+    ) {
+        let (renderer, ctx, size) = R::new(window, width, height, scale_factor).await;
+        self.1.open_window(id, ctx, size.0, size.1).await;
+        self.2.insert(id, renderer);
+    }
+    async fn on_window_event<W: WindowHandle>(&mut self, id: WindowId, event: WindowEvent<W>) {
+        if let Some(renderer) = self.2.get_mut(&id) {renderer.on_event(&mut self.1, id, event).await;}
+    }
+    async fn close_window(&mut self, id: WindowId) {
+        let Some(renderer) = self.2.remove(&id) else {return;};
+        if let Some(ctx) = self.1.close_window(id).await {renderer.close(ctx).await;}
     }
-    async fn close(self) {let ctx = self.1.close().await; self.0.close(ctx).await;}
+    fn window_requests(&mut self) -> Vec<WindowRequest> {self.1.window_requests()}
 }
 
 
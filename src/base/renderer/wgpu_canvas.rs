@@ -1,7 +1,7 @@
 use wgpu_canvas::{ImageAtlas, FontAtlas};
 
 use super::{Renderer, RenderAppTrait, HasLifeEvents};
-use crate::base::window::{WindowHandle, WindowEvent};
+use crate::base::window::{WindowHandle, WindowEvent, WindowId};
 
 pub use wgpu_canvas::{Shape, Color, Area, Text, Span, Cursor, CursorAction, Align, Font, Image};
 pub use crate::base::window::{MouseState, KeyboardState, NamedKey, SmolStr, Key};
@@ -25,6 +25,8 @@ pub enum Event {
     Keyboard{key: Key, state: KeyboardState},
     Resumed{width: f32, height: f32},
     Paused,
+    ///The user asked to close the window, which closes after this event
+    CloseRequested,
     Tick
 }
 
@@ -138,9 +140,9 @@ impl Renderer for Canvas {
     }
         
     async fn on_event<W: WindowHandle, A: RenderAppTrait<Self>>(
-        &mut self, app: &mut A, event: WindowEvent<W>
+        &mut self, app: &mut A, id: WindowId, event: WindowEvent<W>
     ) {
-        let ctx = app.ctx();
+        let Some(ctx) = app.window_ctx(id) else {return;};
         let draw =  matches!(event, WindowEvent::Tick);
         let r_event = match event {
             WindowEvent::Resized{width, height, scale_factor} => {
@@ -164,10 +166,11 @@ impl Renderer for Canvas {
                 Event::Resumed{width: size.0, height: size.1}
             },
             WindowEvent::Paused => Event::Paused,
+            WindowEvent::CloseRequested => Event::CloseRequested,
             WindowEvent::Tick => Event::Tick
         };
-        app.on_event(r_event).await;
-        let ctx = app.ctx();
+        app.on_window_event(id, r_event).await;
+        let Some(ctx) = app.window_ctx(id) else {return;};
         if draw {self.draw(&mut ctx.image, &mut ctx.font, ctx.components.drain(..).collect::<Vec<_>>());}
     }
 
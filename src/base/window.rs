@@ -10,6 +10,21 @@ impl<W: HasWindowHandle + HasDisplayHandle + Send + Sync + 'static> WindowHandle
 //TODO: Replace with non winit structs
 pub use winit_crate::keyboard::{NamedKey, SmolStr, Key};
 
+///Identifies a window of the app, MAIN being the one it starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WindowId(pub u64);
+
+impl WindowId {
+    pub const MAIN: WindowId = WindowId(0);
+}
+
+///Change to the open windows asked for by the app
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowRequest {
+    Open{id: WindowId, title: String, size: Option<(u32, u32)>},
+    Close(WindowId),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum WindowEvent<W: WindowHandle> {
     Resized{width: u32, height: u32, scale_factor: f64},
@@ -17,6 +32,8 @@ pub enum WindowEvent<W: WindowHandle> {
     Keyboard{key: Key, state: KeyboardState},
     Resumed{window: W, width: u32, height: u32, scale_factor: f64},
     Paused,
+    ///The user asked to close a window other than the main one, which closes after this event
+    CloseRequested,
     Tick
 }
 
@@ -32,6 +49,15 @@ pub trait WindowAppTrait {
     ) -> impl Future<Output = Self> where Self: Sized;
     fn on_event<W: WindowHandle>(&mut self, event: WindowEvent<W>) -> impl Future<Output = ()>;
     fn close(self) -> impl Future<Output = ()>;
+
+    ///Opens a window the app asked for through window_requests
+    fn open_window<W: WindowHandle>(
+        &mut self, id: WindowId, window: W, width: u32, height: u32, scale_factor: f64
+    ) -> impl Future<Output = ()>;
+    fn on_window_event<W: WindowHandle>(&mut self, id: WindowId, event: WindowEvent<W>) -> impl Future<Output = ()>;
+    fn close_window(&mut self, id: WindowId) -> impl Future<Output = ()>;
+    ///Windows to open or close, taken after every event
+    fn window_requests(&mut self) -> Vec<WindowRequest>;
 }
 
 mod winit;
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::path::PathBuf;
 use std::sync::{Mutex, Arc};
@@ -6,14 +7,18 @@ use std::time::{Duration, Instant};
 use winit_crate::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit_crate::event::{ElementState, WindowEvent as WinitWindowEvent, TouchPhase, Touch, MouseScrollDelta};
 use winit_crate::application::ApplicationHandler;
-use winit_crate::window::{Window, WindowId};
+use winit_crate::window::{Window, WindowId as WinitWindowId};
+use winit_crate::dpi::LogicalSize;
 
 #[cfg(target_os="android")]
 use winit_crate::platform::android::activity::AndroidApp;
 #[cfg(target_os="android")]
 use winit_crate::platform::android::EventLoopBuilderExtAndroid;
 
-use super::{WindowAppTrait, WindowEvent, MouseState, KeyboardState};
+use super::{WindowAppTrait, WindowEvent, WindowHandle, WindowId, WindowRequest, MouseState, KeyboardState};
+
+///How often the time spent handling window events is logged
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Default)]
 pub struct BlockingFuture<T: 'static>(Arc<Mutex<Option<T>>>);
@@ -23,13 +28,25 @@ impl<T: 'static> BlockingFuture<T> {
     }
 }
 
-pub struct BlockingRuntime;
+///Executor driving the WindowApp, created once and reused for every window event
+pub struct BlockingRuntime {
+    #[cfg(not(target_arch="wasm32"))]
+    runtime: tokio::runtime::Runtime,
+}
+
 impl BlockingRuntime {
-    pub fn block_on<T: 'static>(task: impl Future<Output = T>) -> BlockingFuture<T> {
+    pub fn new() -> Self {
+        BlockingRuntime{
+            #[cfg(not(target_arch="wasm32"))]
+            runtime: tokio::runtime::Builder::new_current_thread().enable_time().enable_io().build().unwrap()
+        }
+    }
+
+    pub fn block_on<T: 'static>(&self, task: impl Future<Output = T>) -> BlockingFuture<T> {
         #[cfg(not(target_arch="wasm32"))]
         let future = BlockingFuture(Arc::new(Mutex::new(Some(
             //Take current thread and block untill future completes
-            tokio::runtime::Builder::new_current_thread().enable_time().enable_io().build().unwrap().block_on(task)
+            self.runtime.block_on(task)
         ))));
 
         #[cfg(target_arch="wasm32")]
@@ -44,8 +61,104 @@ impl BlockingRuntime {
     }
 }
 
+impl Default for BlockingRuntime {
+    fn default() -> Self {Self::new()}
+}
+
+///Count, total and longest time spent handling each kind of window event since the last report
+#[derive(Default)]
+struct EventTimings {
+    since: Option<Instant>,
+    events: HashMap<&'static str, (u32, Duration, Duration)>,
+}
+
+impl EventTimings {
+    fn record(&mut self, name: &'static str, elapsed: Duration) {
+        let (count, total, max) = self.events.entry(name).or_default();
+        *count += 1;
+        *total += elapsed;
+        *max = (*max).max(elapsed);
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if since.elapsed() < REPORT_INTERVAL {return;}
+        for (name, (count, total, max)) in self.events.drain() {
+            log::debug!("{}: {} events, avg {:?}, max {:?}", name, count, total / count, max);
+        }
+        self.since = Some(Instant::now());
+    }
+
+    fn name<W: WindowHandle>(event: &WindowEvent<W>) -> &'static str {
+        match event {
+            WindowEvent::Resized{..} => "Resized",
+            WindowEvent::Mouse{..} => "Mouse",
+            WindowEvent::Keyboard{..} => "Keyboard",
+            WindowEvent::Resumed{..} => "Resumed",
+            WindowEvent::Paused => "Paused",
+            WindowEvent::CloseRequested => "CloseRequested",
+            WindowEvent::Tick => "Tick",
+        }
+    }
+}
+
+///A window the app opened besides the main one
+struct Secondary {
+    id: WindowId,
+    window: Arc<Window>,
+    size: (u32, u32),
+    scale_factor: f64,
+    mouse: (u32, u32, f32, f32),
+}
+
+///Translates the pointer and keyboard events handled alike in every window,
+///tracking the cursor position and scroll in mouse
+fn input(mouse: &mut (u32, u32, f32, f32), event: WinitWindowEvent) -> Option<WindowEvent<Arc<Window>>> {
+    match event {
+        WinitWindowEvent::CursorMoved{position, ..} => {
+            if mouse.0 != position.x as u32 && mouse.1 != position.y as u32 {
+                mouse.0 = position.x as u32;
+                mouse.1 = position.y as u32;
+                return Some(WindowEvent::Mouse{position: (mouse.0, mouse.1), state: MouseState::Moved});
+            }
+            None
+        },
+        WinitWindowEvent::MouseInput{state, ..} => {
+            Some(WindowEvent::Mouse{position: (mouse.0, mouse.1), state: match state {
+                ElementState::Pressed => MouseState::Pressed,
+                ElementState::Released => MouseState::Released,
+            }})
+        },
+        WinitWindowEvent::MouseWheel{delta, phase, ..} => {
+            let position = (mouse.0, mouse.1);
+            if let TouchPhase::Moved = phase {
+                let pos = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (x, y),
+                    MouseScrollDelta::PixelDelta(p) => (p.x as f32, p.y as f32),
+                };
+                let scroll_speed = 0.2; // Tune this to adjust sensitivity
+                mouse.2 += -pos.0 * scroll_speed;
+                mouse.3 += -pos.1 * scroll_speed;
+                return Some(WindowEvent::Mouse{position, state: MouseState::Scroll(mouse.2, mouse.3)});
+            }
+            if let TouchPhase::Ended = phase {
+                mouse.2 = 0.0;
+                mouse.3 = 0.0;
+            }
+            None
+        },
+        WinitWindowEvent::KeyboardInput{event, ..} => {
+            Some(WindowEvent::Keyboard{
+                key: event.logical_key, state: match event.state {
+                ElementState::Pressed => KeyboardState::Pressed,
+                ElementState::Released => KeyboardState::Released,
+            }})
+        },
+        _ => None
+    }
+}
+
 pub struct Winit<A: WindowAppTrait + 'static> {
     scale_factor: f64,
+    runtime: BlockingRuntime,
+    timings: EventTimings,
     future: Option<BlockingFuture<A>>,
     window: Option<Arc<Window>>,
     prev_touch: Option<(f64, f64)>,
@@ -54,12 +167,15 @@ pub struct Winit<A: WindowAppTrait + 'static> {
     size: (u32, u32),
     name: Option<PathBuf>,
     app: Option<A>,
+    windows: HashMap<WinitWindowId, Secondary>,
 }
 
 impl<A: WindowAppTrait + 'static> Winit<A> {
     pub fn new(name: PathBuf) -> Self {
         Winit{
             scale_factor: 0.0,
+            runtime: BlockingRuntime::new(),
+            timings: EventTimings::default(),
             future: None,
             window: None,
             prev_touch: None,
@@ -68,6 +184,7 @@ impl<A: WindowAppTrait + 'static> Winit<A> {
             size: (0, 0),
             name: Some(name),
             app: None,
+            windows: HashMap::new(),
         }
     }
 
@@ -101,7 +218,7 @@ impl<A: WindowAppTrait + 'static> Winit<A> {
 
     fn close(&mut self) {
         self.check_future();
-        BlockingRuntime::block_on(self.app.take().unwrap().close());
+        self.runtime.block_on(self.app.take().unwrap().close());
     }
 
     fn window(&self) -> Arc<Window> {self.window.clone().unwrap()}
@@ -110,20 +227,89 @@ impl<A: WindowAppTrait + 'static> Winit<A> {
         if let Some(future) = self.future.take() {self.app = Some(future.unwrap());}
     }
 
-    fn app_event(&mut self, event: WindowEvent<Arc<Window>>) {
+    fn app_task<F: Future<Output = A> + 'static>(&mut self, task: impl FnOnce(A) -> F) {
         self.check_future();
-        if self.app.is_none() {return;}//Already Closed
-        let mut app = self.app.take().unwrap();
-        self.future = Some(BlockingRuntime::block_on(async move {
-            app.on_event(event).await;
+        let Some(app) = self.app.take() else {return;};//Already Closed
+        self.future = Some(self.runtime.block_on(task(app)));
+    }
+
+    fn app_event(&mut self, event: WindowEvent<Arc<Window>>) {self.dispatch(WindowId::MAIN, event);}
+
+    fn dispatch(&mut self, id: WindowId, event: WindowEvent<Arc<Window>>) {
+        #[cfg(not(target_arch="wasm32"))]
+        let (name, start) = (EventTimings::name(&event), Instant::now());
+        self.app_task(move |mut app| async move {
+            match id {
+                WindowId::MAIN => app.on_event(event).await,
+                id => app.on_window_event(id, event).await
+            }
             app
-        }));
+        });
+        #[cfg(not(target_arch="wasm32"))]
+        if log::log_enabled!(log::Level::Debug) {self.timings.record(name, start.elapsed());}
+    }
+
+    ///Opens and closes the windows the app asked for while handling the last events
+    fn window_requests(&mut self, event_loop: &ActiveEventLoop) {
+        self.check_future();
+        let Some(requests) = self.app.as_mut().map(|app| app.window_requests()) else {return;};
+        for request in requests {
+            match request {
+                WindowRequest::Open{id, title, size} => {
+                    let mut attributes = Window::default_attributes().with_title(title);
+                    if let Some((width, height)) = size {attributes = attributes.with_inner_size(LogicalSize::new(width, height));}
+                    let window = match event_loop.create_window(attributes) {
+                        Ok(window) => Arc::new(window),
+                        Err(error) => {log::error!("Could not open window: {}", error); continue;}
+                    };
+                    let size = window.inner_size();
+                    let scale_factor = window.scale_factor();
+                    self.windows.insert(window.id(), Secondary{
+                        id, window: window.clone(), size: size.into(), scale_factor, mouse: (0, 0, 0.0, 0.0)
+                    });
+                    self.app_task(move |mut app| async move {
+                        app.open_window(id, window, size.width, size.height, scale_factor).await;
+                        app
+                    });
+                },
+                WindowRequest::Close(id) => self.close_window(id)
+            }
+        }
+    }
+
+    fn close_window(&mut self, id: WindowId) {
+        self.windows.retain(|_, window| window.id != id);
+        self.app_task(move |mut app| async move {app.close_window(id).await; app});
+    }
+
+    fn secondary_event(&mut self, i: WinitWindowId, event: WinitWindowEvent) {
+        let Some(window) = self.windows.get_mut(&i) else {return;};
+        let id = window.id;
+        let event = match event {
+            WinitWindowEvent::CloseRequested => {
+                self.dispatch(id, WindowEvent::CloseRequested);
+                return self.close_window(id);
+            },
+            WinitWindowEvent::RedrawRequested => Some(WindowEvent::Tick),
+            WinitWindowEvent::Resized(size) => {
+                window.size = size.into();
+                Some(WindowEvent::Resized{width: size.width, height: size.height, scale_factor: window.scale_factor})
+            },
+            WinitWindowEvent::ScaleFactorChanged{scale_factor, ..} => {
+                window.scale_factor = scale_factor;
+                Some(WindowEvent::Resized{width: window.size.0, height: window.size.1, scale_factor})
+            },
+            event => input(&mut window.mouse, event)
+        };
+        if let Some(event) = event {self.dispatch(id, event);}
     }
 }
 
 impl<A: WindowAppTrait + 'static> ApplicationHandler for Winit<A> {
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        self.window_requests(event_loop);
         if self.window.is_some() {self.window().request_redraw();}
+        for secondary in self.windows.values() {secondary.window.request_redraw();}
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
@@ -142,7 +328,7 @@ impl<A: WindowAppTrait + 'static> ApplicationHandler for Winit<A> {
                 window: window.clone(), width: size.width, height: size.height, scale_factor
             });
         } else {
-            self.future = Some(BlockingRuntime::block_on(A::new(
+            self.future = Some(self.runtime.block_on(A::new(
                 self.name.take().unwrap(), window, size.width, size.height, scale_factor
             )))
         }
@@ -152,7 +338,7 @@ impl<A: WindowAppTrait + 'static> ApplicationHandler for Winit<A> {
         self.app_event(WindowEvent::Paused);
     }
 
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, i: WindowId, event: WinitWindowEvent) {
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, i: WinitWindowId, event: WinitWindowEvent) {
         self.window().request_redraw();
         if i == self.window().id() {
             match event {
@@ -276,45 +462,10 @@ impl<A: WindowAppTrait + 'static> ApplicationHandler for Winit<A> {
                         }
                     }
                 },                
-                WinitWindowEvent::CursorMoved{position, ..} => {
-                    if self.mouse.0 != position.x as u32 && self.mouse.1 != position.y as u32 {
-                        self.mouse.0 = position.x as u32;
-                        self.mouse.1 = position.y as u32;
-                        self.app_event(WindowEvent::Mouse{position: (self.mouse.0, self.mouse.1), state: MouseState::Moved});
-                    }
-                },
-                WinitWindowEvent::MouseInput{state, ..} => {
-                    self.app_event(WindowEvent::Mouse{position: (self.mouse.0, self.mouse.1), state: match state {
-                        ElementState::Pressed => MouseState::Pressed,
-                        ElementState::Released => MouseState::Released,
-                    }});
-                },
-                WinitWindowEvent::MouseWheel{delta, phase, ..} => {
-                    let position = (self.mouse.0, self.mouse.1);
-                    if let TouchPhase::Moved = phase {
-                        let pos = match delta {
-                            MouseScrollDelta::LineDelta(x, y) => (x, y),
-                            MouseScrollDelta::PixelDelta(p) => (p.x as f32, p.y as f32),
-                        };
-                        let scroll_speed = 0.2; // Tune this to adjust sensitivity
-                        self.mouse.2 += -pos.0 * scroll_speed;
-                        self.mouse.3 += -pos.1 * scroll_speed;
-                        self.app_event(WindowEvent::Mouse{position, state: MouseState::Scroll(self.mouse.2, self.mouse.3)});
-                    }
-                    if let TouchPhase::Ended = phase {
-                        self.mouse.2 = 0.0;
-                        self.mouse.3 = 0.0;
-                    }
-                },
-                WinitWindowEvent::KeyboardInput{event, ..} => {
-                    self.app_event(WindowEvent::Keyboard{
-                        key: event.logical_key, state: match event.state {
-                        ElementState::Pressed => KeyboardState::Pressed,
-                        ElementState::Released => KeyboardState::Released,
-                    }});
-                },
-                _ => {}
+                event => if let Some(event) = input(&mut self.mouse, event) {self.app_event(event);}
             }
+        } else {
+            self.secondary_event(i, event);
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::base;
 use crate::base::driver::state::State;
+use crate::base::driver::cache::Storage;
 use crate::base::driver::runtime::Tasks;
 use crate::base::driver::camera::Camera;
 use crate::base::BaseAppTrait;
@@ -20,6 +21,12 @@ pub use crate::base::HeadlessContext;
 /// - initialization logic
 /// - runtime event handling
 pub trait App {
+    /// Where the [`Cache`](crate::Cache) keeps its values, [`Storage::Sqlite`] unless overridden.
+    const STORAGE: Storage = Storage::Sqlite;
+
+    /// Approximate size in bytes the [`Cache`](crate::Cache) is kept under, unlimited unless overridden.
+    const CACHE_QUOTA: Option<u64> = None;
+
     /// Runs any background (headless) tasks associated with this app.
     ///
     /// This is typically used to spawn tasks like networking, data loading,
@@ -154,6 +161,12 @@ impl<A: App> BaseAppTrait<Canvas> for CanvasApp<A> {
     /// Logging level for this application; can be adjusted as needed.
     const LOG_LEVEL: log::Level = log::Level::Error;
 
+    /// Storage chosen by the [`App`] for the [`Cache`](crate::Cache).
+    const STORAGE: Storage = A::STORAGE;
+
+    /// Cache quota chosen by the [`App`].
+    const CACHE_QUOTA: Option<u64> = A::CACHE_QUOTA;
+
     /// Launches background tasks for the app if any.
     ///
     /// Called once at initialization.
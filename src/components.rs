@@ -7,11 +7,18 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::time::Instant;
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 
 use crate::base;
 use base::{BaseAppTrait, HeadlessContext};
-use base::driver::runtime::Tasks;
+use base::window::WindowId;
+use base::driver::runtime::{Task, TaskHandle, TaskMetrics, Tasks};
+use base::driver::clock::Clock;
+use base::driver::ipc::Ipc;
 use base::driver::state::State;
+use base::driver::cache::{Cache, CacheError, CacheKey, Storage, Watch};
+use base::driver::dump::Dump;
+use base::driver::state::Field;
 use base::driver::share::Share;
 use base::driver::photo_picker::{PhotoPicker, ImageOrientation};
 use base::driver::safe_area::SafeAreaInsets;
@@ -25,7 +32,7 @@ use include_dir::{Dir, DirEntry};
 
 mod events;
 pub use events::{
-    Events, OnEvent, Event, TickEvent,
+    Events, OnEvent, Event, TickEvent, TaskFailed, CloseRequested,
     MouseEvent, MouseState,
     KeyboardEvent, KeyboardState,
     NamedKey, Key, SmolStr,
@@ -47,6 +54,12 @@ use drawable::_Drawable;
 /// Type alias for a list of directories containing UI assets. (e.g. images, fonts, etc.)
 pub type Assets = Vec<Dir<'static>>;
 
+/// Cache and state watches registered on the [`Context`], each returning its next change as an event.
+type Watches = Vec<Box<dyn FnMut() -> Option<Box<dyn Event>>>>;
+
+/// Builds the root component of a window requested with [`Context::open_window`] once it is open.
+type WindowBuilder = Box<dyn FnOnce(&mut Context) -> Box<dyn Drawable>>;
+
 /// The [`Context`] struct encapsulates the global state of an application,
 /// including plugin management, asset storage, event dispatching, and
 /// access to the rendering and state context provided by the [`base`] crate.
@@ -54,6 +67,8 @@ pub struct Context {
     plugins: Plugins,
     assets: Assets,
     events: Events,
+    watches: Watches,
+    windows: HashMap<WindowId, WindowBuilder>,
     base_context: base::Context<Canvas>,
 }
 
@@ -64,6 +79,8 @@ impl Context {
             plugins: Plugins::new(),
             assets: Assets::new(),
             events: Events::new(),
+            watches: Vec::new(),
+            windows: HashMap::new(),
             base_context,
         }
     }
@@ -94,6 +111,140 @@ impl Context {
         self.base_context.state()
     }
 
+    /// Starts running a background task and returns a [`TaskHandle`] to control it.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - Any type implementing the [`Task`] trait.
+    pub fn spawn(&mut self, task: impl Task + 'static) -> TaskHandle {
+        self.base_context.spawn(task)
+    }
+
+    /// Returns the [`Clock`] timing the running tasks.
+    ///
+    /// Components that depend on time should read it from here so they can be
+    /// driven by a [`ManualClock`](crate::ManualClock) in tests.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.base_context.clock()
+    }
+
+    /// Triggers a [`Changed<F>`](crate::Changed) event whenever the value of type `F` stored with
+    /// [`Cache::set`](crate::Cache::set) changes, so components can react to tasks updating the cache.
+    pub fn watch_cache<F: Field + Clone + 'static>(&mut self) {
+        let watch = self.base_context.cache().watch::<F>();
+        self.watch(watch);
+    }
+
+    /// Triggers a [`Changed<F>`](crate::Changed) event whenever any value in the [`Table`](crate::Table) of `F` changes.
+    pub fn watch_table<F: Field + Clone + 'static>(&mut self) {
+        let watch = self.base_context.cache().table::<F>().watch_all();
+        self.watch(watch);
+    }
+
+    /// Triggers a [`StateChanged<F>`](crate::StateChanged) event whenever a new value of type `F`
+    /// is set on the [`State`], so components can react to it instead of re-reading it every tick.
+    pub fn watch_state<F: Field + Clone + 'static>(&mut self) {
+        let mut watch = self.base_context.state().watch::<F>();
+        self.watches.push(Box::new(move || watch.try_next().map(|changed| Box::new(changed) as Box<dyn Event>)));
+    }
+
+    /// Triggers a [`Changed<F>`](crate::Changed) event for every change seen by the given [`Watch`].
+    ///
+    /// # Arguments
+    ///
+    /// * `watch` - A [`Watch`] on a key or a whole namespace of the [`Cache`](crate::Cache).
+    pub fn watch<F: Field + Clone + 'static>(&mut self, mut watch: Watch<F>) {
+        self.watches.push(Box::new(move || watch.try_next().map(|changed| Box::new(changed) as Box<dyn Event>)));
+    }
+
+    /// Exports the [`State`] and [`Cache`](crate::Cache) as a human readable [`Dump`](crate::Dump),
+    /// to attach to a bug report with [`Dump::to_json`](crate::Dump::to_json).
+    pub fn dump(&self) -> Result<Dump, CacheError> {
+        self.base_context.dump()
+    }
+
+    /// Replaces the contents of the [`State`] and [`Cache`](crate::Cache) with those of a [`Dump`],
+    /// to reproduce a bug report locally.
+    ///
+    /// # Arguments
+    ///
+    /// * `dump` - A [`Dump`], usually read with [`Dump::from_json`].
+    pub fn load(&mut self, dump: Dump) -> Result<(), CacheError> {
+        self.base_context.load(dump)
+    }
+
+    /// Returns the run counts, durations, missed intervals and last errors of the tasks.
+    ///
+    /// Tasks that finished or were disabled after failing stay listed for a while, marked as such.
+    ///
+    /// Useful for logging or drawing a debug overlay when tasks slow the app down.
+    pub fn task_metrics(&self) -> Vec<TaskMetrics> {
+        self.base_context.task_metrics()
+    }
+
+    /// Returns the channel to the other desktop process, if there is one.
+    ///
+    /// In the UI process this reaches the background process started with a CLI argument,
+    /// so components can send it commands and show the notifications it sends back.
+    pub fn ipc(&self) -> Option<&Ipc> {
+        self.base_context.ipc()
+    }
+
+    /// Sends a command to the running tasks, received with [`HeadlessContext::receive`].
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - Any value that can be sent across threads.
+    pub fn send<C: Send + 'static>(&mut self, command: C) {
+        self.base_context.send(command)
+    }
+
+    /// Opens another window, such as an inspector or a detached panel, with its own component tree.
+    ///
+    /// The window opens once the current event is handled, calling `root` with this [`Context`]
+    /// to build its root component. Fonts and images added while building or drawing it belong to
+    /// that window. Its tree receives its own input and tick events, and a [`CloseRequested`] event
+    /// when the user closes it. Plugins, the [`State`] and the [`Cache`](crate::Cache) are shared
+    /// with the main window.
+    ///
+    /// # Arguments
+    ///
+    /// * `title` - The title of the window.
+    /// * `size` - The size of the window in logical pixels, or `None` for the platform default.
+    /// * `root` - Builds the root [`Drawable`] component of the window.
+    ///
+    /// # Returns
+    ///
+    /// The [`WindowId`] to close the window with [`Context::close_window`].
+    pub fn open_window(
+        &mut self,
+        title: &str,
+        size: Option<(u32, u32)>,
+        root: impl FnOnce(&mut Context) -> Box<dyn Drawable> + 'static,
+    ) -> WindowId {
+        let id = self.base_context.open_window(title, size);
+        self.windows.insert(id, Box::new(root));
+        id
+    }
+
+    /// Closes a window opened with [`Context::open_window`] once the current event is handled.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The [`WindowId`] returned by [`Context::open_window`].
+    pub fn close_window(&mut self, id: WindowId) {
+        self.windows.remove(&id);
+        self.base_context.close_window(id)
+    }
+
+    /// Takes the oldest message of type `M` posted by a task with [`HeadlessContext::post`].
+    ///
+    /// Events posted with [`HeadlessContext::trigger_event`] are delivered to the component tree
+    /// instead, and only the most recent few hundred messages of each type are kept.
+    pub fn receive<M: Send + 'static>(&mut self) -> Option<M> {
+        self.base_context.receive()
+    }
+
     /// Includes a static directory of assets into the asset manager.
     ///
     /// # Arguments
@@ -223,6 +374,19 @@ impl Context {
     }
 }
 
+impl HeadlessContext {
+    /// Posts an event from a task to the component tree.
+    ///
+    /// The event is delivered through [`Context::trigger_event`] on the next tick.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - Any type implementing the [`Event`] trait that can be sent across threads.
+    pub fn trigger_event(&self, event: impl Event + Send) {
+        self.post::<Box<dyn Event + Send>>(Box::new(event));
+    }
+}
+
 impl AsMut<CanvasContext> for Context {
     fn as_mut(&mut self) -> &mut CanvasContext {self.base_context.as_mut()}
 }
@@ -275,6 +439,35 @@ pub type Plugins = HashMap<TypeId, Box<dyn std::any::Any>>;
 ///
 /// An implementing type defines how background tasks, plugins, and the root UI component are initialized.
 pub trait App {
+    /// Where the [`Cache`](crate::Cache) keeps its values. Defaults to [`Storage::Sqlite`].
+    ///
+    /// Use [`Storage::Memory`] to keep nothing on disk.
+    const STORAGE: Storage = Storage::Sqlite;
+
+    /// Approximate size in bytes the [`Cache`](crate::Cache) may grow to before its least recently
+    /// used entries are evicted. Defaults to no limit.
+    const CACHE_QUOTA: Option<u64> = None;
+
+    /// Whether Ctrl+Z undoes and Ctrl+Shift+Z or Ctrl+Y redo the tracked [`State`] fields,
+    /// with Cmd in place of Ctrl on Apple platforms. Defaults to off.
+    ///
+    /// Fields are tracked with [`State::track`]. The shortcut key events are not passed to components.
+    const UNDO_SHORTCUTS: bool = false;
+
+    /// Optionally sets the key the [`Cache`] encrypts its values with, before the persisted
+    /// [`State`] is restored or any task runs. Defaults to no key, storing values unencrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `_cache` - The opened [`Cache`], whose [`salt`](Cache::salt) derives a key with [`CacheKey::from_passphrase`].
+    ///
+    /// # Returns
+    ///
+    /// The [`CacheKey`] to encrypt with, or `None`.
+    fn cache_key(_cache: &Cache) -> Option<CacheKey> {
+        None
+    }
+
     /// Optionally defines background tasks to run before rendering or plugin setup.
     ///
     /// # Arguments
@@ -321,6 +514,34 @@ pub trait App {
     fn new(ctx: &mut Context) -> impl Future<Output = Box<dyn Drawable>>;
 }
 
+/// The component tree of a window opened with [`Context::open_window`].
+struct Window {
+    /// The root drawable component of the window.
+    app: Box<dyn Drawable>,
+
+    /// The size of the window in logical pixels.
+    screen: (f32, f32),
+
+    /// A built and layout-sized version of the root component.
+    sized_app: SizedBranch,
+
+    /// The canvas context of the window, swapped into the [`Context`] while the window is handled.
+    canvas: CanvasContext,
+
+    /// The events queued for the window, swapped into the [`Context`] with its canvas.
+    events: Events,
+}
+
+impl Window {
+    /// Swaps the canvas and event queue of the window with those of the [`Context`].
+    ///
+    /// Called once before handling the window and once after to put back those of the main window.
+    fn swap(&mut self, ctx: &mut Context) {
+        std::mem::swap(ctx.as_canvas(), &mut self.canvas);
+        std::mem::swap(&mut ctx.events, &mut self.events);
+    }
+}
+
 /// [`ComponentApp<A>`] is a generic application wrapper that integrates an [`App`] implementation
 /// into the framework's [`BaseAppTrait`] system for event-driven UI updates and drawing.
 ///
@@ -343,12 +564,78 @@ pub struct ComponentApp<A: App> {
 
     /// Time at which the last frame started rendering. Used for frame timing.
     time: Instant,
+
+    /// Whether the command key (Ctrl, or Cmd on Apple platforms) and Shift are held.
+    modifiers: (bool, bool),
+
+    /// The windows opened with [`Context::open_window`] and their component trees.
+    windows: HashMap<WindowId, Window>,
+}
+
+impl<A: App> ComponentApp<A> {
+    /// Tracks the modifier keys and applies the undo and redo shortcuts to the [`State`].
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key completed a shortcut and should not be passed to components.
+    fn shortcut(&mut self, key: &Key, state: &KeyboardState) -> bool {
+        let pressed = matches!(state, KeyboardState::Pressed);
+        let command = if cfg!(any(target_os = "macos", target_os = "ios")) {NamedKey::Super} else {NamedKey::Control};
+        match key {
+            Key::Named(named) if *named == command => self.modifiers.0 = pressed,
+            Key::Named(NamedKey::Shift) => self.modifiers.1 = pressed,
+            Key::Character(c) if self.modifiers.0 => {
+                let state = self.ctx.state();
+                return match c.to_lowercase().as_str() {
+                    "z" if self.modifiers.1 => {if pressed {state.redo();} true},
+                    "z" => {if pressed {state.undo();} true},
+                    "y" => {if pressed {state.redo();} true},
+                    _ => false
+                };
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Passes the queued events to a component tree, then rebuilds its layout and draws it.
+    fn frame(ctx: &mut Context, app: &mut Box<dyn Drawable>, sized_app: &mut SizedBranch, screen: (f32, f32)) {
+        while let Some(event) = ctx.events.pop_front() {
+            if let Some(event) = event
+                .pass(ctx, vec![((0.0, 0.0), sized_app.0)])
+                .remove(0)
+            {
+                app.event(ctx, sized_app.clone(), event);
+            }
+        }
+
+        let size_request = _Drawable::request_size(&**app, ctx);
+        *sized_app = app.build(ctx, screen, size_request);
+
+        app.draw(
+            ctx,
+            sized_app.clone(),
+            (0.0, 0.0),
+            (0.0, 0.0, screen.0, screen.1),
+        );
+    }
 }
 
 impl<A: App> BaseAppTrait<Canvas> for ComponentApp<A> {
     /// Controls the default logging level for this app.
     const LOG_LEVEL: log::Level = log::Level::Error;
 
+    /// Uses the storage chosen by the [`App`].
+    const STORAGE: Storage = A::STORAGE;
+
+    /// Uses the cache quota chosen by the [`App`].
+    const CACHE_QUOTA: Option<u64> = A::CACHE_QUOTA;
+
+    /// Uses the cache key chosen by the [`App`].
+    fn cache_key(cache: &Cache) -> Option<CacheKey> {
+        A::cache_key(cache)
+    }
+
     /// Runs any headless background tasks defined by the [`App`].
     async fn background_tasks(ctx: &mut HeadlessContext) -> Tasks {
         A::background_tasks(ctx).await
@@ -382,6 +669,8 @@ impl<A: App> BaseAppTrait<Canvas> for ComponentApp<A> {
                 sized_app,
                 _p: std::marker::PhantomData::<A>,
                 time: Instant::now(),
+                modifiers: (false, false),
+                windows: HashMap::new(),
             },
             tasks,
         )
@@ -401,6 +690,9 @@ impl<A: App> BaseAppTrait<Canvas> for ComponentApp<A> {
                     .push_back(Box::new(MouseEvent { position: Some(position), state }));
             }
             canvas::Event::Keyboard { key, state } => {
+                if A::UNDO_SHORTCUTS && self.shortcut(&key, &state) {
+                    return;
+                }
                 self.ctx
                     .events
                     .push_back(Box::new(KeyboardEvent { key, state }));
@@ -411,25 +703,19 @@ impl<A: App> BaseAppTrait<Canvas> for ComponentApp<A> {
 
                 self.app.event(&mut self.ctx, self.sized_app.clone(), Box::new(TickEvent));
 
-                while let Some(event) = self.ctx.events.pop_front() {
-                    if let Some(event) = event
-                        .pass(&mut self.ctx, vec![((0.0, 0.0), self.sized_app.0)])
-                        .remove(0)
-                    {
-                        self.app.event(&mut self.ctx, self.sized_app.clone(), event);
+                while let Some(event) = self.ctx.base_context.receive::<Box<dyn Event + Send>>() {
+                    self.ctx.events.push_back(event);
+                }
+                while let Some(failed) = self.ctx.base_context.receive::<TaskFailed>() {
+                    self.ctx.events.push_back(Box::new(failed));
+                }
+                for watch in &mut self.ctx.watches {
+                    while let Some(event) = watch() {
+                        self.ctx.events.push_back(event);
                     }
                 }
 
-                let size_request = _Drawable::request_size(&*self.app, &mut self.ctx);
-                self.sized_app =
-                    self.app.build(&mut self.ctx, self.screen, size_request);
-
-                self.app.draw(
-                    &mut self.ctx,
-                    self.sized_app.clone(),
-                    (0.0, 0.0),
-                    (0.0, 0.0, self.screen.0, self.screen.1),
-                );
+                Self::frame(&mut self.ctx, &mut self.app, &mut self.sized_app, self.screen);
             }
             _ => {}
         }
@@ -444,6 +730,74 @@ impl<A: App> BaseAppTrait<Canvas> for ComponentApp<A> {
     fn ctx(&mut self) -> &mut base::Context<Canvas> {
         &mut self.ctx.base_context
     }
+
+    /// Builds the component tree of a window opened with [`Context::open_window`],
+    /// with the window's canvas context swapped in.
+    async fn open_window(&mut self, id: WindowId, mut canvas: CanvasContext, width: f32, height: f32) {
+        let Some(root) = self.ctx.windows.remove(&id) else {
+            return;
+        };
+        let mut events = Events::new();
+        std::mem::swap(self.ctx.as_canvas(), &mut canvas);
+        std::mem::swap(&mut self.ctx.events, &mut events);
+
+        let mut app = root(&mut self.ctx);
+        let screen = (width, height);
+        let size_request = _Drawable::request_size(&*app, &mut self.ctx);
+        let sized_app = app.build(&mut self.ctx, screen, size_request);
+
+        let mut window = Window { app, screen, sized_app, canvas, events };
+        window.swap(&mut self.ctx);
+        self.windows.insert(id, window);
+    }
+
+    /// Handles the events of a window opened with [`Context::open_window`] like those of the
+    /// main window, leaving background events and watches to the main window's tick.
+    fn on_window_event(&mut self, id: WindowId, event: canvas::Event) {
+        if let canvas::Event::Keyboard { key, state } = &event {
+            if A::UNDO_SHORTCUTS && self.shortcut(key, state) {
+                return;
+            }
+        }
+        let Some(window) = self.windows.get_mut(&id) else {
+            return;
+        };
+        match event {
+            canvas::Event::Resized { width, height }
+            | canvas::Event::Resumed { width, height } => {
+                window.screen = (width, height);
+            }
+            canvas::Event::Mouse { position, state } => {
+                window.events.push_back(Box::new(MouseEvent { position: Some(position), state }));
+            }
+            canvas::Event::Keyboard { key, state } => {
+                window.events.push_back(Box::new(KeyboardEvent { key, state }));
+            }
+            canvas::Event::CloseRequested => {
+                window.swap(&mut self.ctx);
+                window.app.event(&mut self.ctx, window.sized_app.clone(), Box::new(CloseRequested));
+                window.swap(&mut self.ctx);
+            }
+            canvas::Event::Tick => {
+                window.swap(&mut self.ctx);
+                window.app.event(&mut self.ctx, window.sized_app.clone(), Box::new(TickEvent));
+                Self::frame(&mut self.ctx, &mut window.app, &mut window.sized_app, window.screen);
+                window.swap(&mut self.ctx);
+            }
+            canvas::Event::Paused => {}
+        }
+    }
+
+    /// Returns the canvas context of a window opened with [`Context::open_window`].
+    fn window_ctx(&mut self, id: WindowId) -> Option<&mut CanvasContext> {
+        self.windows.get_mut(&id).map(|window| &mut window.canvas)
+    }
+
+    /// Drops the component tree of a closing window and returns its canvas context.
+    fn close_window(&mut self, id: WindowId) -> Option<CanvasContext> {
+        self.ctx.windows.remove(&id);
+        self.windows.remove(&id).map(|window| window.canvas)
+    }
 }
 
 
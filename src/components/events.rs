@@ -1,5 +1,9 @@
 use super::{Context};
 pub use crate::base::renderer::wgpu_canvas::{MouseState, KeyboardState, NamedKey, Key, SmolStr};
+pub use crate::base::driver::runtime::TaskFailed;
+pub use crate::base::driver::cache::Changed;
+pub use crate::base::driver::state::StateChanged;
+use crate::base::driver::state::Field;
 use downcast_rs::{Downcast, impl_downcast};
 
 use std::fmt::Debug;
@@ -60,3 +64,31 @@ impl Event for TickEvent {
         children.into_iter().map(|_| Some(Box::new(*self) as Box<dyn Event>)).collect()
     }
 }
+
+/// Sent to the component tree of a window opened with [`Context::open_window`]
+/// when the user closes it, just before it closes.
+#[derive(Debug, Clone, Copy)]
+pub struct CloseRequested;
+impl Event for CloseRequested {
+    fn pass(self: Box<Self>, _ctx: &mut Context, children: Vec<((f32, f32), (f32, f32))>) -> Vec<Option<Box<dyn Event>>> {
+        children.into_iter().map(|_| Some(Box::new(*self) as Box<dyn Event>)).collect()
+    }
+}
+
+impl<F: Field + Clone + 'static> Event for Changed<F> {
+    fn pass(self: Box<Self>, _ctx: &mut Context, children: Vec<((f32, f32), (f32, f32))>) -> Vec<Option<Box<dyn Event>>> {
+        children.into_iter().map(|_| Some(self.clone() as Box<dyn Event>)).collect()
+    }
+}
+
+impl<F: Field + Clone + 'static> Event for StateChanged<F> {
+    fn pass(self: Box<Self>, _ctx: &mut Context, children: Vec<((f32, f32), (f32, f32))>) -> Vec<Option<Box<dyn Event>>> {
+        children.into_iter().map(|_| Some(self.clone() as Box<dyn Event>)).collect()
+    }
+}
+
+impl Event for TaskFailed {
+    fn pass(self: Box<Self>, _ctx: &mut Context, children: Vec<((f32, f32), (f32, f32))>) -> Vec<Option<Box<dyn Event>>> {
+        children.into_iter().map(|_| Some(self.clone() as Box<dyn Event>)).collect()
+    }
+}
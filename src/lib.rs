@@ -2,11 +2,17 @@
 
 mod base;
 pub use base::{BackgroundApp, HeadlessContext, BaseApp};
-pub use base::window::WindowApp;
+pub use base::window::{WindowApp, WindowId, WindowRequest};
 pub use base::renderer::RenderApp;
-pub use base::driver::runtime::{Task, Tasks, async_trait};
-pub use base::driver::state::{State, Field};
-pub use base::driver::cache::Cache;
+pub use base::driver::runtime::{Task, Tasks, TaskHandle, TaskError, TaskFailed, TaskMetrics, RetryPolicy, Schedule, Cron, InvalidCron, async_trait};
+pub use base::driver::clock::{Clock, SystemClock, ManualClock};
+pub use base::driver::ipc::Ipc;
+pub use base::driver::state::{State, Field, StateChanged, StateWatch};
+pub use base::driver::dump::{Dump, DumpedEntry, CacheDump};
+pub use base::driver::schema::{Schema, Migration, DuplicateIdent, Encoding};
+pub use base::driver::cache::{Cache, CacheError, Namespace, Table, Storage, Backend, Entry, Usage, MemoryBackend, Change, Changed, Watch, Transaction, Op, CacheKey};
+#[cfg(not(target_arch = "wasm32"))]
+pub use base::driver::cache::{SqliteBackend, FileBackend};
 pub use base::driver::app_support::ApplicationSupport;
 pub use base::driver::photo_picker::ImageOrientation;
 pub use base::driver::cloud::CloudStorage;